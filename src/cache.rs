@@ -0,0 +1,205 @@
+use anyhow::*;
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde::*;
+use std::{
+    fs,
+    path::*,
+    sync::Mutex,
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    collections::Item, config::Config, config_edit::COMPLETION_MESSAGE, paths::write_atomic, sources::Source,
+};
+
+/// Refreshes which have to finish before qtai exits
+static REFRESHES: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Items of a source saved on disk
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CacheFile {
+    pub collection: String,
+    pub items: IndexMap<String, Item>,
+}
+
+/// Directory of the cache, usually `~/.cache/qtai`
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("qtai"))
+}
+
+/// Cache file of a collection. A new source definition gets a new file.
+pub fn cache_file(collection: &str, source: &Source) -> Option<PathBuf> {
+    let source = serde_json::to_string(source).ok()?;
+    let hash = fnv1a([collection.as_bytes(), &[0], source.as_bytes()].concat());
+    let name: String = collection
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    Some(cache_dir()?.join(format!("{}-{:016x}.json", name, hash)))
+}
+
+/// 64-bit FNV-1a hash. Unlike the standard library hasher, it stays the same
+/// across Rust releases, so cache files outlive a toolchain upgrade.
+fn fnv1a(bytes: impl AsRef<[u8]>) -> u64 {
+    bytes.as_ref().iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Reads a cache file, or None if there is none
+pub fn load(path: &Path) -> Option<CacheFile> {
+    let text = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// How long ago a cache file was written
+pub fn age(path: &Path) -> Option<Duration> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    SystemTime::now().duration_since(modified).ok()
+}
+
+/// Saves items for the next run
+pub fn store(path: &Path, collection: &str, items: &IndexMap<String, Item>) -> Result<()> {
+    let text = serde_json::to_string(&CacheFile {
+        collection: collection.to_string(),
+        items: items.clone(),
+    })?;
+    write_atomic(path, text)?;
+    Ok(())
+}
+
+/// Uses cached items younger than the ttl. Older ones are shown right away
+/// while they are refreshed in the background. Without a cache, the items are generated now.
+pub fn stale_while_revalidate<F>(
+    path: PathBuf,
+    collection: &str,
+    ttl: Duration,
+    generate: F,
+) -> Result<IndexMap<String, Item>>
+where
+    F: FnOnce() -> Result<IndexMap<String, Item>> + Send + 'static,
+{
+    let Some(cached) = load(&path) else {
+        let items = generate()?;
+        let _ = store(&path, collection, &items);
+        return Ok(items);
+    };
+    if age(&path).is_none_or(|a| a >= ttl) {
+        let collection = collection.to_string();
+        let handle = thread::spawn(move || match generate() {
+            anyhow::Result::Ok(items) => {
+                let _ = store(&path, &collection, &items);
+            }
+            // The stale items are still shown, but the source needs fixing
+            Err(e) => eprintln!(
+                "{}",
+                format!("Cannot refresh items of collection \"{}\": {:#}", collection, e).red()
+            ),
+        });
+        REFRESHES.lock().unwrap().push(handle);
+    }
+    Ok(cached.items)
+}
+
+/// Waits for background refreshes, so they are not cut off when qtai exits
+pub fn finish_refreshes() {
+    let handles: Vec<_> = REFRESHES.lock().unwrap().drain(..).collect();
+    for h in handles {
+        let _ = h.join();
+    }
+}
+
+/// Every cache file with what is in it
+pub fn cache_files() -> Vec<(PathBuf, Option<CacheFile>)> {
+    let Some(entries) = cache_dir().and_then(|d| fs::read_dir(d).ok()) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect();
+    files.sort();
+    files.into_iter().map(|p| (p.clone(), load(&p))).collect()
+}
+
+/// Prints every cache file, how old it is and whether the collection still uses it
+pub fn status(config: &Config) -> Result<()> {
+    let dir = cache_dir().context("Cache directory not found.")?;
+    println!("Cache directory: {}", dir.display());
+    let files = cache_files();
+    if files.is_empty() {
+        println!("No cached items.");
+    }
+    for (path, file) in files {
+        let Some(file) = file else {
+            println!("{} {}", path.display(), "(unreadable)".red());
+            continue;
+        };
+        let age = age(&path).unwrap_or_default();
+        let ttl = config.collections.get(&file.collection).and_then(|c| {
+            let source = c.source.as_ref()?;
+            (cache_file(&file.collection, source)? == path)
+                .then(|| c.cache_ttl.unwrap_or(0))
+        });
+        let state = match ttl {
+            None => "unused".dimmed(),
+            Some(t) if age < Duration::from_secs(t) => "fresh".green(),
+            Some(_) => "stale".yellow(),
+        };
+        println!(
+            "\"{}\": {} items, updated {} ago ({})",
+            file.collection,
+            file.items.len(),
+            display_duration(age),
+            state
+        );
+    }
+    Ok(())
+}
+
+/// Removes the cache files of collections matching the input, or every cache file
+pub fn clear(collections_input: &[String]) -> Result<()> {
+    let mut removed = 0;
+    for (path, file) in cache_files() {
+        let matches = collections_input.is_empty()
+            || file.is_some_and(|f| {
+                collections_input
+                    .iter()
+                    .any(|c| f.collection.to_lowercase().contains(&c.to_lowercase()))
+            });
+        if matches {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    println!("Removed {} cache files.", removed);
+    println!("{}", COMPLETION_MESSAGE);
+    Ok(())
+}
+
+/// Shortens a duration to its biggest unit, e.g. `3m` or `2d`
+fn display_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m", seconds / 60),
+        3600..86400 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_names_use_a_stable_hash() {
+        // Published FNV-1a test vectors
+        assert_eq!(fnv1a(""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a("foobar"), 0x85944171f73967e8);
+    }
+}
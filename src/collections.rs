@@ -1,34 +1,232 @@
 use indexmap::*;
 use serde::*;
+use std::path::PathBuf;
+
+use crate::sources::Source;
 
 ///Collection stores a profile which has a default runner
 /// and collection of items to pair with the runner
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Default)]
 pub struct Collection {
+    /// Shown next to the collection name in `qtai run --two-stage`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
     pub default_runner: Option<String>,
+    /// Menu used by qtai run for this collection, either a command or a name from `[menus]`
+    pub menu: Option<String>,
+    /// Collection to inherit the runner, menu and items from. Local items override inherited ones.
+    pub extends: Option<String>,
+    /// Where to generate more items from when running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+    /// Seconds generated items are used from the cache before they are generated again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_ttl: Option<u64>,
+    /// Set to false to keep launches out of the history, so frecency leaves the items alone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<bool>,
+    /// How several items picked at once with `qtai run --multi` are run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multi: Option<MultiRun>,
+    /// Other commands the items can be run with, picked from an action menu with `qtai run --actions`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub actions: IndexMap<String, String>,
+    /// Nested collections. When loading, they are moved to the top level under paths like `projects/rust`.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub collections: IndexMap<String, Collection>,
+    /// File the collection was loaded from, if it is not the root config
+    #[serde(skip)]
+    pub file: Option<PathBuf>,
     #[serde(flatten)]
-    pub items: IndexMap<String, String>,
+    pub items: IndexMap<String, Item>,
+}
+
+/// How several picked items of a collection are run
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MultiRun {
+    /// Once for each item
+    #[default]
+    Each,
+    /// Once with every value as `$1..$n`
+    All,
+}
+
+/// An item is either a plain value or an inline table with extra information
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum Item {
+    Plain(String),
+    Detailed(Box<ItemEntry>),
+}
+
+/// The inline table form of an item, e.g. `github = { value = "https://github.com", runner = "..." }`
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Default)]
+pub struct ItemEntry {
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Answers offered when asking for a parameter, keyed by parameter name
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub suggestions: IndexMap<String, Vec<String>>,
+    /// Actions of the item, which take precedence over collection actions with the same name
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub actions: IndexMap<String, String>,
+}
+
+impl Item {
+    /// The value given to the runner
+    pub fn value(&self) -> &str {
+        match self {
+            Item::Plain(v) => v,
+            Item::Detailed(e) => &e.value,
+        }
+    }
+
+    /// Item level runner, which takes precedence over the collection runner
+    pub fn runner(&self) -> Option<&str> {
+        match self {
+            Item::Plain(_) => None,
+            Item::Detailed(e) => e.runner.as_deref(),
+        }
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        match self {
+            Item::Plain(_) => None,
+            Item::Detailed(e) => e.description.as_deref(),
+        }
+    }
+
+    pub fn icon(&self) -> Option<&str> {
+        match self {
+            Item::Plain(_) => None,
+            Item::Detailed(e) => e.icon.as_deref(),
+        }
+    }
+
+    pub fn tags(&self) -> &[String] {
+        match self {
+            Item::Plain(_) => &[],
+            Item::Detailed(e) => &e.tags,
+        }
+    }
+
+    pub fn actions(&self) -> Option<&IndexMap<String, String>> {
+        match self {
+            Item::Plain(_) => None,
+            Item::Detailed(e) => Some(&e.actions),
+        }
+    }
+
+    /// Answers offered for a parameter such as `{?query}`
+    pub fn suggestions(&self, parameter: &str) -> &[String] {
+        match self {
+            Item::Plain(_) => &[],
+            Item::Detailed(e) => e.suggestions.get(parameter).map_or(&[], |s| s.as_slice()),
+        }
+    }
+
+    /// Checks if the value, description or tags contain the query, ignorant of case
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        self.value().to_lowercase().contains(&query)
+            || self
+                .description()
+                .is_some_and(|d| d.to_lowercase().contains(&query))
+            || self.tags().iter().any(|t| t.to_lowercase().contains(&query))
+    }
+
+    /// Formats the item for list and search output
+    pub fn display(&self, key: &str) -> String {
+        let mut res = format!("\"{}\": \"{}\"", key, self.value());
+        if let Some(r) = self.runner() {
+            res += &format!(" (runner: '{}')", r);
+        }
+        if let Some(d) = self.description() {
+            res += &format!(" - {}", d);
+        }
+        if !self.tags().is_empty() {
+            res += &format!(" [{}]", self.tags().join(", "));
+        }
+        res
+    }
+}
+
+impl From<ItemEntry> for Item {
+    fn from(entry: ItemEntry) -> Self {
+        Item::Detailed(Box::new(entry))
+    }
+}
+
+impl From<String> for Item {
+    fn from(value: String) -> Self {
+        Item::Plain(value)
+    }
+}
+
+/// Path of the parent of a nested collection, e.g. `projects` for `projects/rust`
+pub fn parent_name(name: &str) -> Option<&str> {
+    name.rsplit_once('/').map(|p| p.0)
+}
+
+/// Keys which are settings of a collection table, so items cannot use them
+pub const RESERVED_KEYS: [&str; 10] = [
+    "source",
+    "history",
+    "menu",
+    "actions",
+    "collections",
+    "multi",
+    "extends",
+    "description",
+    "cache_ttl",
+    "default_runner",
+];
+
+/// Whether a key would be read as a collection setting instead of an item
+pub fn is_reserved_key(key: &str) -> bool {
+    RESERVED_KEYS.contains(&key)
+}
+
+/// Numbers a key which cannot be used as is, like `docs (2)`, with the first number not taken
+pub fn numbered_key(key: &str, is_taken: impl Fn(&str) -> bool) -> String {
+    (2..)
+        .map(|n| format!("{} ({})", key, n))
+        .find(|k| !is_taken(k))
+        .expect("there is always a free number")
+}
+
+/// Paths of all parents of a nested collection, closest first
+pub fn parent_names(name: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(parent_name(name), |p| parent_name(p))
 }
 
 impl Collection {
     /// Outputs a vector of items which matches with query, ignorant of case
-    pub fn query_items(self: &Self, query: &str) -> Vec<(&str, &str)> {
+    pub fn query_items(&self, query: &str) -> Vec<(&str, &Item)> {
         self.items
             .iter()
-            .filter(|i| {
-                i.0.to_lowercase().contains(&query.to_lowercase())
-                    || i.1.to_lowercase().contains(&query.to_lowercase())
-            })
-            .map(|i| (i.0.as_str(), i.1.as_str()))
+            .filter(|i| i.0.to_lowercase().contains(&query.to_lowercase()) || i.1.matches(query))
+            .map(|i| (i.0.as_str(), i.1))
             .collect()
     }
 
     pub fn template() -> Self {
         let mut template_items = IndexMap::new();
-        template_items.insert("example key".to_string(), "example value".to_string());
+        template_items.insert(
+            "example key".to_string(),
+            Item::Plain("example value".to_string()),
+        );
         Self {
-            default_runner: None,
             items: template_items,
+            ..Default::default()
         }
     }
 }
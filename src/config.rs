@@ -1,35 +1,376 @@
 use crate::collections::*;
+use crate::history::{Frecency, Sort};
+use crate::paths::expand_tilde;
 use anyhow::*;
 use colored::*;
 use dialoguer::Select;
+use figment::{Figment, Metadata, Source};
 use indexmap::*;
+use itertools::Itertools;
 use serde::*;
+use std::fs;
+use std::path::*;
+
+/// Version of the config format written by this qtai
+pub const CONFIG_VERSION: u32 = 3;
 
 /// Configuration
 #[derive(Default, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct Config {
+    /// Version of the config format. Missing means the file is from before versioning.
+    #[serde(default)]
+    pub version: u32,
+    /// Other config files to merge, relative to this file. Globs are allowed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
     pub default_runner: String,
     pub default_menu: String,
+    /// Named runners, referenced elsewhere as `@name`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub runners: IndexMap<String, String>,
+    /// Named menu commands, usable by name in place of a menu command
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub menus: IndexMap<String, Menu>,
+    /// Order of items in qtai run
+    #[serde(default, skip_serializing_if = "Sort::is_default")]
+    pub sort: Sort,
+    #[serde(default, skip_serializing_if = "Frecency::is_default")]
+    pub frecency: Frecency,
+    /// Pick a collection first, then one of its items, as with `qtai run --two-stage`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub two_stage: bool,
     pub collections: IndexMap<String, Collection>,
+    /// Where settings came from, keyed by their dotted path
+    #[serde(skip)]
+    pub sources: IndexMap<String, String>,
+    /// Collections written with a `/` in their own name, which only nesting may add
+    #[serde(skip)]
+    pub slashed_names: Vec<String>,
+    /// Config files in the order they are merged, the root config first
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+}
+
+/// A named menu, either a command or a table such as `{ command = "rofi -dmenu", icons = true }`
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum Menu {
+    Plain(String),
+    Detailed {
+        command: String,
+        /// Whether the menu understands icons sent with the extended dmenu protocol
+        #[serde(default)]
+        icons: bool,
+    },
+}
+
+impl Menu {
+    pub fn command(&self) -> &str {
+        match self {
+            Menu::Plain(c) => c,
+            Menu::Detailed { command, .. } => command,
+        }
+    }
+
+    pub fn icons(&self) -> bool {
+        match self {
+            Menu::Plain(_) => false,
+            Menu::Detailed { icons, .. } => *icons,
+        }
+    }
 }
 
 impl Config {
     pub fn template() -> Self {
         Self {
+            version: CONFIG_VERSION,
             default_runner: "".to_string(),
             default_menu: "dmenu".to_string(),
-            collections: IndexMap::default(),
+            ..Default::default()
+        }
+    }
+
+    /// Lists the files making up the config, in merge order: the root config,
+    /// its includes and then `conf.d/*.toml` next to the root config.
+    pub fn files(config_path: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = vec![config_path.to_path_buf()];
+        let config_dir = config_path.parent().unwrap_or(Path::new(""));
+        let includes = fs::read_to_string(config_path)
+            .ok()
+            .and_then(|f| toml::from_str::<toml::Table>(&f).ok())
+            .and_then(|t| t.get("include").cloned())
+            .and_then(|i| i.try_into::<Vec<String>>().ok())
+            .unwrap_or_default();
+        let patterns = includes
+            .iter()
+            .map(|i| config_dir.join(expand_tilde(i)))
+            .chain([config_dir.join("conf.d").join("*.toml")]);
+        for pattern in patterns {
+            let pattern = pattern.to_string_lossy();
+            let mut matches: Vec<PathBuf> = glob::glob(&pattern)
+                .with_context(|| format!("Invalid include pattern \"{}\"", pattern))?
+                .filter_map(|p| p.ok())
+                .filter(|p| p.is_file() && !files.contains(p))
+                .collect();
+            matches.sort();
+            files.append(&mut matches);
+        }
+        Ok(files)
+    }
+
+    /// Moves nested collections to the top level, named by their path like `projects/rust`.
+    /// Parents always come before their children.
+    pub fn flattened(mut self) -> Self {
+        fn visit(name: String, mut collection: Collection, flat: &mut IndexMap<String, Collection>) {
+            let children = std::mem::take(&mut collection.collections);
+            flat.insert(name.clone(), collection);
+            for (child, c) in children {
+                visit(format!("{}/{}", name, child), c, flat);
+            }
+        }
+        let mut flat = IndexMap::new();
+        let mut slashed = Vec::new();
+        for (name, c) in std::mem::take(&mut self.collections) {
+            find_slashed(&name, &name, &c, &mut slashed);
+            visit(name, c, &mut flat);
         }
+        self.collections = flat;
+        self.slashed_names = slashed;
+        self
+    }
+
+    /// Dotted key of a collection in the config file, e.g. `collections.projects.collections.rust`
+    pub fn collection_key(name: &str) -> String {
+        format!("collections.{}", name.replace('/', ".collections."))
+    }
+
+    /// Records which file each collection came from. The last file defining a collection wins,
+    /// same as merging. Collections from the root config have no file recorded.
+    pub fn with_files(mut self, files: &[PathBuf]) -> Self {
+        self.files = files.to_vec();
+        for file in files.iter().skip(1) {
+            let names: Vec<String> = fs::read_to_string(file)
+                .ok()
+                .and_then(|f| toml::from_str::<toml::Table>(&f).ok())
+                .and_then(|t| t.get("collections").and_then(|c| c.as_table()).cloned())
+                .map(|c| c.keys().cloned().collect())
+                .unwrap_or_default();
+            for name in names {
+                // Nested collections live in the same file as their parent
+                let prefix = format!("{}/", name);
+                for c in self
+                    .collections
+                    .iter_mut()
+                    .filter(|c| *c.0 == name || c.0.starts_with(&prefix))
+                {
+                    c.1.file = Some(file.clone());
+                }
+            }
+        }
+        self
+    }
+
+    /// Records where the runner and menu settings came from, using figment metadata
+    pub fn with_sources(mut self, figment: &Figment) -> Self {
+        let keys = ["default_runner".to_string(), "default_menu".to_string()]
+            .into_iter()
+            .chain(self.collections.keys().flat_map(|c| {
+                [
+                    format!("{}.default_runner", Config::collection_key(c)),
+                    format!("{}.menu", Config::collection_key(c)),
+                ]
+            }));
+        for key in keys {
+            let Some(metadata) = figment.find_metadata(&key) else {
+                continue;
+            };
+            let source = Config::describe_source(metadata, &key);
+            self.sources.insert(key, source);
+        }
+        self
+    }
+
+    /// Names where a setting came from, such as a file or an environment variable
+    pub fn describe_source(metadata: &Metadata, key: &str) -> String {
+        match &metadata.source {
+            Some(Source::File(p)) => format!("file {}", p.display()),
+            Some(Source::Code(_)) => "CLI flag --set".to_string(),
+            _ if metadata.name.contains("environment") => format!(
+                "environment variable QTAI_{}",
+                key.to_uppercase().replace('.', "__")
+            ),
+            _ => metadata.name.to_string(),
+        }
+    }
+
+    /// Formats where a setting came from, or nothing if it is unknown
+    fn display_source(&self, key: &str) -> String {
+        match self.sources.get(key) {
+            Some(s) => format!(" [from {}]", s).dimmed().to_string(),
+            None => "".to_string(),
+        }
+    }
+
+    /// The file where a collection is defined
+    pub fn collection_file<'a>(&'a self, name: &str, config_path: &'a Path) -> &'a Path {
+        self.collections
+            .get(name)
+            .and_then(|c| c.file.as_deref())
+            .unwrap_or(config_path)
+    }
+
+    /// The file where an item of a collection is defined. A collection can be spread over
+    /// several files, and like merging, the last file with the key wins.
+    pub fn item_file<'a>(&'a self, collection: &str, key: &str, config_path: &'a Path) -> &'a Path {
+        let has_key = |file: &Path| {
+            let Some(root) = fs::read_to_string(file)
+                .ok()
+                .and_then(|f| toml::from_str::<toml::Table>(&f).ok())
+            else {
+                return false;
+            };
+            collection
+                .split('/')
+                .try_fold(&root, |t, part| t.get("collections")?.as_table()?.get(part)?.as_table())
+                .is_some_and(|t| t.contains_key(key))
+        };
+        self.files
+            .iter()
+            .rev()
+            .find(|f| has_key(f))
+            .map(|f| f.as_path())
+            .unwrap_or_else(|| self.collection_file(collection, config_path))
+    }
+
+    /// Resolves a runner written as `@name` using the runner registry.
+    /// Any other runner is already a command and is returned as is.
+    pub fn resolve_runner<'a>(&'a self, runner: &'a str) -> Result<&'a str> {
+        match runner.strip_prefix('@') {
+            Some(name) => self
+                .runners
+                .get(name)
+                .map(|r| r.as_str())
+                .with_context(|| format!("Cannot find runner \"{}\" in [runners].", name)),
+            None => Ok(runner),
+        }
+    }
+
+    /// Resolves a menu name (optionally written as `@name`) using the menu registry.
+    /// Anything not in the registry is treated as a menu command.
+    pub fn resolve_menu<'a>(&'a self, menu: &'a str) -> &'a str {
+        let name = menu.strip_prefix('@').unwrap_or(menu);
+        self.menus.get(name).map(|m| m.command()).unwrap_or(menu)
+    }
+
+    /// Whether a menu is a named menu which shows icons. Menu commands never get icons,
+    /// since most menus print the icon data back.
+    pub fn menu_icons(&self, menu: &str) -> bool {
+        let name = menu.strip_prefix('@').unwrap_or(menu);
+        self.menus.get(name).is_some_and(|m| m.icons())
+    }
+
+    /// Formats a runner for display, showing what a named runner resolves to
+    pub fn display_runner(&self, runner: &str) -> String {
+        match self.resolve_runner(runner).ok() {
+            Some(r) if r != runner => format!("{} ('{}')", runner, r),
+            Some(r) => format!("'{}'", r),
+            None => format!("{} (not found in [runners])", runner),
+        }
+    }
+
+    /// Follows `extends` from a collection, returning the collection and its ancestors, closest first.
+    /// Fails on a missing collection or an inheritance cycle.
+    pub fn ancestors<'a>(&'a self, name: &'a str) -> Result<Vec<&'a str>> {
+        let mut chain: Vec<&str> = Vec::new();
+        let mut current = Some(name);
+        while let Some(c) = current {
+            if chain.contains(&c) {
+                chain.push(c);
+                return Err(anyhow!("Inheritance cycle: {}", chain.iter().map(|c| format!("\"{}\"", c)).join(" -> ")));
+            }
+            let collection = self.collections.get(c).with_context(|| match chain.last() {
+                Some(child) => format!("Collection \"{}\" extends \"{}\", which does not exist.", child, c),
+                None => format!("Cannot find collection \"{}\".", c),
+            })?;
+            chain.push(c);
+            current = collection.extends.as_deref();
+        }
+        Ok(chain)
+    }
+
+    /// Returns the config with `extends` applied to every collection
+    pub fn resolved(&self) -> Result<Config> {
+        let mut resolved = self.clone();
+        for (name, collection) in resolved.collections.iter_mut() {
+            let mut items = IndexMap::new();
+            let mut actions = IndexMap::new();
+            // Farthest ancestor first, so closer collections override
+            for ancestor in self.ancestors(name)?.iter().rev() {
+                let a = &self.collections[*ancestor];
+                items.extend(a.items.clone());
+                actions.extend(a.actions.clone());
+                collection.default_runner = a.default_runner.clone().or(collection.default_runner.take());
+                collection.menu = a.menu.clone().or(collection.menu.take());
+            }
+            collection.items = items;
+            collection.actions = actions;
+        }
+        // Nested collections use the runner, menu and actions of their parent by default.
+        // Parents come first, so they are already resolved.
+        for i in 0..resolved.collections.len() {
+            let (name, collection) = resolved.collections.get_index(i).unwrap();
+            let Some(parent) = parent_name(name).and_then(|p| resolved.collections.get(p)) else {
+                continue;
+            };
+            let default_runner = collection.default_runner.clone().or(parent.default_runner.clone());
+            let menu = collection.menu.clone().or(parent.menu.clone());
+            let mut actions = parent.actions.clone();
+            actions.extend(collection.actions.clone());
+            let collection = &mut resolved.collections[i];
+            collection.default_runner = default_runner;
+            collection.menu = menu;
+            collection.actions = actions;
+        }
+        Ok(resolved)
+    }
+
+    /// Loads the generated items of the collections matching the input. Static items win over
+    /// generated ones with the same key. A source which fails is reported and skipped.
+    pub fn with_source_items(mut self, collections_input: &[String], selective: bool) -> Self {
+        let names: Vec<String> = self
+            .filter_collections(collections_input, selective)
+            .into_iter()
+            .filter(|c| c.1.source.is_some())
+            .map(|c| c.0.to_string())
+            .collect();
+        for name in names {
+            let collection = &mut self.collections[&name];
+            let Some(source) = &collection.source else {
+                continue;
+            };
+            match source.cached_items(&name, collection.cache_ttl) {
+                anyhow::Result::Ok(items) => {
+                    for (key, item) in items {
+                        collection.items.entry(key).or_insert(item);
+                    }
+                }
+                Err(e) => eprintln!(
+                    "{}",
+                    format!("Cannot load items of collection \"{}\": {:#}", name, e).red()
+                ),
+            }
+        }
+        self
     }
 
     /// This method filters collections based on query
     pub fn filter_collections(
-        self: &Self,
+        &self,
         collections_input: &[String],
         selective: bool,
     ) -> IndexMap<&str, &Collection> {
         // If there are no input, we input all of them
-        if collections_input.len() == 0 {
+        if collections_input.is_empty() {
             self.collections
                 .iter()
                 .map(|i| (i.0.as_str(), i.1))
@@ -58,19 +399,69 @@ impl Config {
     }
 
     /// This method filters collections based on arguments and then prints them.
-    pub fn list_collections(self: &Self, collections_input: &[String], selective: bool) -> Result<()> {
+    /// With `show_sources`, each setting is followed by where it came from.
+    pub fn list_collections(
+        &self,
+        collections_input: &[String],
+        selective: bool,
+        show_sources: bool,
+    ) -> Result<()> {
         let collections: IndexMap<&str, &Collection> = self.filter_collections(collections_input, selective);
-        if collections.len() == 0 {
+        if collections.is_empty() {
             return Err(anyhow!("No collections are found"));
         }
+        let source = |key: &str| {
+            if show_sources {
+                self.display_source(key)
+            } else {
+                "".to_string()
+            }
+        };
 
-        for i in collections {
-            println!("{}", i.0.bold());
-            if i.1.items.len() == 0 {
-                println!("This collection is empty.");
+        if show_sources {
+            println!("Default runner: {}{}", self.display_runner(&self.default_runner), source("default_runner"));
+            println!("Default menu: {}{}", self.default_menu, source("default_menu"));
+            println!();
+        }
+        for i in &collections {
+            // Nested collections are indented under their parent when it is listed too
+            let depth = parent_names(i.0)
+                .filter(|p| collections.contains_key(p))
+                .count();
+            let indent = "  ".repeat(depth);
+            let name = match parent_name(i.0) {
+                Some(p) if collections.contains_key(p) => &i.0[p.len() + 1..],
+                _ => i.0,
+            };
+            let key = Config::collection_key(i.0);
+            println!("{}{}", indent, name.bold());
+            match &i.1.default_runner {
+                Some(r) => println!(
+                    "{}Runner: {}{}",
+                    indent,
+                    self.display_runner(r),
+                    source(&format!("{}.default_runner", key))
+                ),
+                None => println!(
+                    "{}Runner: {} (default)",
+                    indent,
+                    self.display_runner(&self.default_runner)
+                ),
+            }
+            if let Some(m) = &i.1.menu {
+                println!("{}Menu: {}{}", indent, m, source(&format!("{}.menu", key)));
+            }
+            if let Some(e) = &i.1.extends {
+                println!("{}Extends: {}", indent, e);
+            }
+            if let Some(f) = &i.1.file {
+                println!("{}File: {}", indent, f.display());
+            }
+            if i.1.items.is_empty() {
+                println!("{}This collection is empty.", indent);
             }
             for j in &i.1.items {
-                println!("\"{}\": \"{}\"", j.0, j.1);
+                println!("{}{}", indent, j.1.display(j.0));
             }
             println!();
         }
@@ -78,7 +469,7 @@ impl Config {
     }
 
     /// This method filters collections based on (&String) query and outputs a vector of potential candidates
-    pub fn query_collections(self: &Self, query: &str) -> Vec<(&str, &Collection)> {
+    pub fn query_collections(&self, query: &str) -> Vec<(&str, &Collection)> {
         self.collections
             .iter()
             .filter(|i| i.0.to_lowercase().contains(&query.to_lowercase()))
@@ -87,12 +478,12 @@ impl Config {
     }
 
     /// This method searches collections based on query and enables the user to select which one to use, returning the selection.
-    pub fn select_collections(self: &Self, query: &str) -> Result<(&str, &Collection)> {
-        if self.collections.len() == 0 {
+    pub fn select_collections(&self, query: &str) -> Result<(&str, &Collection)> {
+        if self.collections.is_empty() {
             return Err(anyhow!("No collections are found!"));
         }
         let filtered_collections = self.query_collections(query);
-        if filtered_collections.len() == 0 {
+        if filtered_collections.is_empty() {
             Err(anyhow!("Cannot find any collections with that query."))
         } else if filtered_collections.len() == 1 {
             Ok(*filtered_collections.first().unwrap())
@@ -110,23 +501,23 @@ impl Config {
         }
     }
 
-    pub fn query_items(self: &Self, query: &str) -> Vec<(&str, Vec<(&str, &str)>)> {
+    pub fn query_items(&self, query: &str) -> Vec<(&str, Vec<(&str, &Item)>)> {
         self.collections
             .iter()
             .map(|i| (i.0.as_str(), i.1.query_items(query)))
-            .filter(|i| i.1.len() != 0)
+            .filter(|i| !i.1.is_empty())
             .collect()
     }
 
     /// This method searches items based on query and prints results.
-    pub fn search_items(self: &Self, query: &str) -> Result<()> {
+    pub fn search_items(&self, query: &str) -> Result<()> {
         let results = self.query_items(query);
         let mut count = 0;
         for i in results {
             count += 1;
             println!("{}", i.0.bold());
             for j in &i.1 {
-                println!("\"{}\": \"{}\"", j.0, j.1);
+                println!("{}", j.1.display(j.0));
             }
             println!();
         }
@@ -138,13 +529,13 @@ impl Config {
     }
 
     /// A function that queries a set of items. allowing the user to make the final choice, and outputs that collection name and item name.
-    pub fn select_items(self: &Self, query: &str) -> Result<(&str, (&str, &str))> {
-        let results: Vec<(&str, (&str, &str))> = self
+    pub fn select_items(&self, query: &str) -> Result<(&str, (&str, &Item))> {
+        let results: Vec<(&str, (&str, &Item))> = self
             .query_items(query)
             .iter()
             .flat_map(|i| i.1.iter().map(|j| (i.0, *j)))
             .collect();
-        if results.len() == 0 {
+        if results.is_empty() {
             Err(anyhow!("Cannot find any items with that query."))
         } else if results.len() == 1 {
             Ok(*results.first().unwrap())
@@ -155,7 +546,7 @@ impl Config {
                     format!(
                         "\"{}\": \"{}\" from collection \"{}\"",
                         i.1.0.bold(),
-                        i.1.1.bold(),
+                        i.1.1.value().bold(),
                         i.0.bold()
                     )
                 })
@@ -169,3 +560,13 @@ impl Config {
         }
     }
 }
+
+/// Finds collections whose own key has a `/`, which would be mistaken for nesting
+fn find_slashed(path: &str, key: &str, collection: &Collection, slashed: &mut Vec<String>) {
+    if key.contains('/') {
+        slashed.push(path.to_string());
+    }
+    for (child, c) in &collection.collections {
+        find_slashed(&format!("{}/{}", path, child), child, c, slashed);
+    }
+}
@@ -2,31 +2,40 @@ use anyhow::*;
 use colored::Colorize;
 use std::{
     fs::{self, *},
-    io::Write,
     path::*,
 };
-use toml_edit::{DocumentMut};
+use toml_edit::{value, Array, DocumentMut, InlineTable, Table};
 
-use crate::{collections::Collection, config::Config};
+use crate::{
+    collections::{is_reserved_key, parent_name, Collection, Item},
+    config::Config,
+};
 
-const COMPLETION_MESSAGE: &str = "Done (^-^)b";
+pub const COMPLETION_MESSAGE: &str = "Done (^-^)b";
 
 pub fn add_item(
     collection_query: String,
     key: String,
-    value: String,
+    item: Item,
     config_path: PathBuf,
     config: Config,
 ) -> Result<()> {
+    if is_reserved_key(&key) {
+        return Err(anyhow!("Key \"{}\" is a collection setting and cannot name an item.", key));
+    }
+    if let Some(r) = item.runner() {
+        config.resolve_runner(r)?;
+    }
     let selected_collection = config.select_collections(&collection_query)?;
     if selected_collection.1.items.contains_key(&key) {
         Err(anyhow!("Collection already has key."))
     } else {
-        let config_file = fs::read_to_string(&config_path)?;
+        let config_path = config.collection_file(selected_collection.0, &config_path);
+        let config_file = fs::read_to_string(config_path)?;
         let mut doc = config_file
             .parse::<DocumentMut>()
             .expect("invalid document");
-        doc["collections"][selected_collection.0][key] = value.into();
+        collection_table(&mut doc, selected_collection.0)?[key] = item_to_toml(&item);
         write(config_path, doc.to_string())?;
         println!(
             "Key pair added to collection \"{}\".",
@@ -38,40 +47,68 @@ pub fn add_item(
 }
 
 pub fn remove_item(query: &str, config_path: PathBuf, config: Config) -> Result<()> {
-    let selected_item: (&str, (&str, &str)) = config.select_items(query)?;
+    let selected_item: (&str, (&str, &Item)) = config.select_items(query)?;
     println!(
         "Deleting \"{}\": \"{}\" from collection \"{}\"",
-        selected_item.1.0, selected_item.1.1, selected_item.0
+        selected_item.1.0,
+        selected_item.1.1.value(),
+        selected_item.0
     );
-    let config_file = fs::read_to_string(&config_path)?;
+    let config_path = config.item_file(selected_item.0, selected_item.1.0, &config_path);
+    let config_file = fs::read_to_string(config_path)?;
     let mut doc = config_file
         .parse::<DocumentMut>()
         .expect("invalid document");
-    doc["collections"][selected_item.0]
-        .as_table_mut()
+    collection_table(&mut doc, selected_item.0)?
+        .as_table_like_mut()
         .context("Trouble converting collection as a table")?
-        .remove(selected_item.1.0);
+        .remove(selected_item.1.0)
+        // Inherited items are defined in the collection they come from
+        .with_context(|| {
+            format!(
+                "Item \"{}\" is not written in collection \"{}\", so it cannot be removed from there.",
+                selected_item.1.0, selected_item.0
+            )
+        })?;
     write(config_path, doc.to_string())?;
     println!("Item removed.");
     println!("{}", COMPLETION_MESSAGE);
     Ok(())
 }
 
-pub fn add_collection(name: String, config_path: PathBuf, config: Config) -> Result<()> {
+pub fn add_collection(
+    name: String,
+    extends: Option<String>,
+    config_path: PathBuf,
+    config: Config,
+) -> Result<()> {
     if config.collections.contains_key(&name) {
         Err(anyhow!("Config already has this key."))
+    } else if parent_name(&name).is_some_and(|p| !config.collections.contains_key(p)) {
+        Err(anyhow!("Parent collection of \"{}\" does not exist.", name))
     } else {
-        // Add new collection to the end of the line.
-        let mut config_file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(config_path)
-            .unwrap();
-
-        // Add extra line at the end to give good spacing
-        if let Err(e) = writeln!(config_file, "[collections.\"{}\"]\n", name) {
-            eprintln!("Couldn't write to file: {}", e);
+        let extends = match extends {
+            Some(q) => {
+                let parent = config.select_collections(&q)?.0;
+                println!("Extending collection: \"{}\"", parent.bold());
+                Some(parent)
+            }
+            None => None,
+        };
+        // Nested collections go to the file of their parent
+        let config_path = match parent_name(&name) {
+            Some(p) => config.collection_file(p, &config_path),
+            None => &config_path,
+        };
+        let config_file = fs::read_to_string(config_path)?;
+        let mut doc = config_file
+            .parse::<DocumentMut>()
+            .expect("invalid document");
+        let table = collection_table_or_insert(&mut doc, &name)?;
+        if let Some(parent) = extends {
+            table["extends"] = value(parent);
         }
+        write(config_path, doc.to_string())?;
         println!("Collection added.");
         println!("{}", COMPLETION_MESSAGE);
         Ok(())
@@ -91,14 +128,19 @@ pub fn remove_collection(
         "Are you sure? This cannot be undone.",
         Some(false),
         || {
-            let config_file = fs::read_to_string(&config_path)?;
+            let config_path = config.collection_file(selected_collection.0, &config_path);
+            let config_file = fs::read_to_string(config_path)?;
             let mut doc = config_file
                 .parse::<DocumentMut>()
                 .expect("invalid document");
-            doc["collections"]
-                .as_table_mut()
+            let (parent, name) = match selected_collection.0.rsplit_once('/') {
+                Some((p, n)) => (&mut collection_table(&mut doc, p)?["collections"], n),
+                None => (&mut doc["collections"], selected_collection.0),
+            };
+            parent
+                .as_table_like_mut()
                 .context("Trouble converting collection as a table")?
-                .remove(selected_collection.0);
+                .remove(name);
             write(config_path, doc.to_string())?;
             println!("Collection \"{}\" removed.", selected_collection.0);
             println!("{}", COMPLETION_MESSAGE);
@@ -111,21 +153,18 @@ pub fn remove_collection(
 /// Generates config file based on the path given
 pub fn generate_config_file(config_path: &PathBuf) -> anyhow::Result<Config> {
     // Checks and creates parent directories
-    match config_path.parent() {
-        Some(d) => {
-            if !d.is_dir() {
-                create_dir_all(d)?;
-                println!("Created parent directories.")
-            }
-        }
-        // None equals root directory
-        None => {}
-    };
+    // None equals root directory
+    if let Some(d) = config_path.parent()
+        && !d.is_dir()
+    {
+        create_dir_all(d)?;
+        println!("Created parent directories.")
+    }
 
     // Creates a new config file
     let config = Config::template();
     write(config_path, toml::to_string_pretty(&config)?)?;
-    let config_file = fs::read_to_string(&config_path)?;
+    let config_file = fs::read_to_string(config_path)?;
     let mut doc = config_file
         .parse::<DocumentMut>()
         .expect("invalid document");
@@ -140,19 +179,22 @@ pub fn generate_config_file(config_path: &PathBuf) -> anyhow::Result<Config> {
 pub fn change_runner(
     new_runner: &str,
     collection_query: Option<String>,
-    config_path: &PathBuf,
+    config_path: &Path,
     config: &Config,
 ) -> Result<()> {
+    // Named runners have to exist before anything refers to them
+    config.resolve_runner(new_runner)?;
     match collection_query {
         Some(q) => {
             let selected_collection: (&str, &Collection) = config.select_collections(&q)?;
             println!("Found collection: \"{}\"", selected_collection.0.bold());
-            let config_file = fs::read_to_string(&config_path)?;
+            let config_path = config.collection_file(selected_collection.0, config_path);
+            let config_file = fs::read_to_string(config_path)?;
             let mut doc = config_file
                 .parse::<DocumentMut>()
                 .expect("invalid document");
             // Ask toml_edit to do the last part in single quotes
-            doc["collections"][selected_collection.0]["default_runner"] = format!("'{}'", new_runner)
+            collection_table(&mut doc, selected_collection.0)?["default_runner"] = format!("'{}'", new_runner)
                 .parse::<toml_edit::Item>()
                 .unwrap();
             write(config_path, doc.to_string())?;
@@ -160,7 +202,7 @@ pub fn change_runner(
             Ok(())
         }
         None => {
-            let config_file = fs::read_to_string(&config_path)?;
+            let config_file = fs::read_to_string(config_path)?;
             let mut doc = config_file
                 .parse::<DocumentMut>()
                 .expect("invalid document");
@@ -176,16 +218,116 @@ pub fn change_runner(
     }
 }
 
-pub fn change_menu(new_menu: &str, config_path: &PathBuf, _: Config) -> Result<()> {
-            let config_file = fs::read_to_string(&config_path)?;
-            let mut doc = config_file
-                .parse::<DocumentMut>()
-                .expect("invalid document");
-            // Ask toml_edit to do the last part in single quotes
-            doc["default_menu"] = format!("'{}'", new_menu)
-                .parse::<toml_edit::Item>()
-                .unwrap();
-            write(config_path, doc.to_string())?;
-        println!("{}", COMPLETION_MESSAGE);
-            Ok(())
+/// Edits menu in config file, collection may or may not be specified
+pub fn change_menu(
+    new_menu: &str,
+    collection_query: Option<String>,
+    config_path: &Path,
+    config: &Config,
+) -> Result<()> {
+    let selected_collection = match collection_query {
+        Some(q) => {
+            let selected_collection: (&str, &Collection) = config.select_collections(&q)?;
+            println!("Found collection: \"{}\"", selected_collection.0.bold());
+            Some(selected_collection.0)
+        }
+        None => None,
+    };
+    let config_path = match selected_collection {
+        Some(c) => config.collection_file(c, config_path),
+        None => config_path,
+    };
+    let config_file = fs::read_to_string(config_path)?;
+    let mut doc = config_file
+        .parse::<DocumentMut>()
+        .expect("invalid document");
+    // Ask toml_edit to do the last part in single quotes
+    let menu_item = format!("'{}'", new_menu)
+        .parse::<toml_edit::Item>()
+        .unwrap();
+    match selected_collection {
+        Some(c) => collection_table(&mut doc, c)?["menu"] = menu_item,
+        None => doc["default_menu"] = menu_item,
+    }
+    write(config_path, doc.to_string())?;
+    println!("{}", COMPLETION_MESSAGE);
+    Ok(())
+}
+
+/// Finds the table of a collection in a document.
+/// Nested collections like `projects/rust` live at `collections.projects.collections.rust`.
+pub fn collection_table<'a>(doc: &'a mut DocumentMut, name: &str) -> Result<&'a mut toml_edit::Item> {
+    name.split('/').try_fold(doc.as_item_mut(), |item, part| {
+        item.get_mut("collections")
+            .and_then(|c| c.get_mut(part))
+            .with_context(|| format!("Cannot find collection \"{}\" in the config file.", name))
+    })
+}
+
+/// Like `collection_table`, but creates the collection and its parents when they are missing.
+/// New parents get no header of their own, only `[collections."a".collections."b"]`.
+pub fn collection_table_or_insert<'a>(
+    doc: &'a mut DocumentMut,
+    name: &str,
+) -> Result<&'a mut toml_edit::Item> {
+    let implicit = || {
+        let mut t = Table::new();
+        t.set_implicit(true);
+        toml_edit::Item::Table(t)
+    };
+    let table = name.split('/').try_fold(doc.as_item_mut(), |item, part| {
+        let collections = item
+            .as_table_mut()
+            .context("Trouble converting collection as a table")?
+            .entry("collections")
+            .or_insert_with(implicit);
+        Ok(collections
+            .as_table_mut()
+            .context("Trouble converting collections as a table")?
+            .entry(part)
+            .or_insert_with(implicit))
+    })?;
+    if let Some(t) = table.as_table_mut() {
+        t.set_implicit(false);
+    }
+    Ok(table)
+}
+
+/// Converts an item into its toml form. Plain items stay plain strings
+/// and detailed items become inline tables.
+pub fn item_to_toml(item: &Item) -> toml_edit::Item {
+    match item {
+        Item::Plain(v) => value(v),
+        Item::Detailed(e) => {
+            let mut table = InlineTable::new();
+            table.insert("value", e.value.as_str().into());
+            if let Some(r) = &e.runner {
+                table.insert("runner", r.as_str().into());
+            }
+            if let Some(d) = &e.description {
+                table.insert("description", d.as_str().into());
+            }
+            if let Some(i) = &e.icon {
+                table.insert("icon", i.as_str().into());
+            }
+            if !e.tags.is_empty() {
+                table.insert("tags", e.tags.iter().collect::<Array>().into());
+            }
+            if !e.suggestions.is_empty() {
+                let mut suggestions = InlineTable::new();
+                for (name, answers) in &e.suggestions {
+                    suggestions.insert(name, answers.iter().collect::<Array>().into());
+                }
+                table.insert("suggestions", suggestions.into());
+            }
+            if !e.actions.is_empty() {
+                let mut actions = InlineTable::new();
+                for (name, command) in &e.actions {
+                    actions.insert(name, command.as_str().into());
+                }
+                table.insert("actions", actions.into());
+            }
+            value(table)
+        }
+    }
 }
@@ -0,0 +1,293 @@
+use anyhow::*;
+use colored::Colorize;
+use indexmap::IndexMap;
+use serde::Serialize;
+
+use crate::{
+    collections::parent_name,
+    config::Config,
+    paths::{expand_tilde, is_program},
+    sources::Source,
+    template::{self, Variables},
+};
+
+/// Shell builtins and keywords which will never be found on PATH
+const SHELL_BUILTINS: [&str; 22] = [
+    ".", ":", "[", "alias", "cd", "command", "echo", "eval", "exec", "exit", "export", "false",
+    "if", "for", "printf", "read", "set", "source", "test", "true", "while", "{",
+];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the config
+#[derive(Serialize, Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    /// Short name of the check which found the problem
+    pub check: &'static str,
+    /// Dotted path of the setting with the problem
+    pub location: String,
+    pub message: String,
+}
+
+/// Checks the config and prints the problems. Errors, and warnings with strict, make this fail.
+pub fn doctor(config: &Config, json: bool, strict: bool) -> Result<()> {
+    let problems = check(config);
+    let errors = problems.iter().filter(|p| p.severity == Severity::Error).count();
+    let warnings = problems.len() - errors;
+
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({
+                "errors": errors,
+                "warnings": warnings,
+                "problems": problems,
+            })
+        );
+    } else {
+        for p in &problems {
+            let severity = match p.severity {
+                Severity::Error => "error".red().bold(),
+                Severity::Warning => "warning".yellow().bold(),
+            };
+            println!("{} [{}] {}: {}", severity, p.check, p.location.bold(), p.message);
+        }
+        println!("{} errors, {} warnings", errors, warnings);
+    }
+
+    if errors > 0 || (strict && warnings > 0) {
+        Err(anyhow!("Config has problems."))
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs every check on the config
+pub fn check(config: &Config) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if config.default_runner.trim().is_empty() {
+        problems.push(Problem {
+            severity: Severity::Error,
+            check: "missing-default-runner",
+            location: "default_runner".to_string(),
+            message: "Default runner is empty, so items without a runner do nothing.".to_string(),
+        });
+    }
+
+    // Menus
+    let mut menus = vec![("default_menu".to_string(), config.resolve_menu(&config.default_menu))];
+    menus.extend(config.menus.iter().map(|m| (format!("menus.{}", m.0), m.1.command())));
+    menus.extend(config.collections.iter().filter_map(|c| {
+        c.1.menu
+            .as_deref()
+            .map(|m| (format!("collections.{}.menu", c.0), config.resolve_menu(m)))
+    }));
+    for (location, menu) in menus {
+        check_command(&mut problems, location, menu);
+    }
+
+    // Sources
+    for c in &config.collections {
+        let location = format!("collections.{}.source", c.0);
+        match &c.1.source {
+            Some(Source::Command(s)) => check_command(&mut problems, location, &s.command),
+            Some(Source::Plugin(p)) if !is_program(&p.program()) => problems.push(Problem {
+                severity: Severity::Error,
+                check: "missing-plugin",
+                location,
+                message: format!("Plugin \"{}\" is not on PATH.", p.program()),
+            }),
+            _ => {}
+        }
+    }
+
+    // Runners
+    let mut runners = vec![("default_runner".to_string(), config.default_runner.as_str())];
+    runners.extend(config.runners.iter().map(|r| (format!("runners.{}", r.0), r.1.as_str())));
+    for c in &config.collections {
+        if let Some(r) = &c.1.default_runner {
+            runners.push((format!("collections.{}.default_runner", c.0), r));
+        }
+        runners.extend(c.1.actions.iter().map(|a| (format!("collections.{}.actions.{}", c.0, a.0), a.1.as_str())));
+        for i in &c.1.items {
+            if let Some(r) = i.1.runner() {
+                runners.push((format!("collections.{}.{}.runner", c.0, i.0), r));
+            }
+            for a in i.1.actions().into_iter().flatten() {
+                runners.push((format!("collections.{}.{}.actions.{}", c.0, i.0, a.0), a.1));
+            }
+        }
+    }
+    for (location, runner) in runners {
+        if runner.trim().is_empty() {
+            continue;
+        }
+        match config.resolve_runner(runner) {
+            // Registry entries are checked on their own
+            anyhow::Result::Ok(r) if r != runner => {}
+            anyhow::Result::Ok(r) => {
+                check_command(&mut problems, location.clone(), r);
+                if let Err(e) = template::expand(r, &Variables::default()) {
+                    problems.push(Problem {
+                        severity: Severity::Error,
+                        check: "invalid-placeholder",
+                        location: location.clone(),
+                        message: e.to_string(),
+                    });
+                }
+                if has_unquoted_argument(r) {
+                    problems.push(Problem {
+                        severity: Severity::Warning,
+                        check: "unquoted-argument",
+                        location,
+                        message: format!("Runner '{}' uses $1 without double quotes, so values with spaces are split.", r),
+                    });
+                }
+            }
+            Err(e) => problems.push(Problem {
+                severity: Severity::Error,
+                check: "unknown-runner",
+                location,
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    // Names
+    for name in &config.slashed_names {
+        problems.push(Problem {
+            severity: Severity::Error,
+            check: "slash-in-name",
+            location: format!("collections.\"{}\"", name),
+            message: "Collection names cannot have a /, since it marks nested collections. Run \"qtai migrate\" to rename it.".to_string(),
+        });
+    }
+
+    // Inheritance
+    for c in config.collections.keys() {
+        if let Err(e) = config.ancestors(c) {
+            problems.push(Problem {
+                severity: Severity::Error,
+                check: "invalid-extends",
+                location: format!("collections.{}.extends", c),
+                message: e.to_string(),
+            });
+        }
+    }
+    let resolved = config.resolved().ok();
+
+    // Items
+    let mut keys: IndexMap<&str, Vec<&str>> = IndexMap::new();
+    let mut values: IndexMap<&str, Vec<&str>> = IndexMap::new();
+    for c in &config.collections {
+        // Inherited items count, but they are only checked where they are defined.
+        // A parent of nested collections is used as a group, so it does not need items.
+        let is_parent = config.collections.keys().any(|n| parent_name(n) == Some(c.0.as_str()));
+        let is_empty = c.1.source.is_none()
+            && !is_parent
+            && match &resolved {
+                Some(r) => r.collections[c.0].items.is_empty(),
+                None => c.1.items.is_empty(),
+            };
+        if is_empty {
+            problems.push(Problem {
+                severity: Severity::Warning,
+                check: "empty-collection",
+                location: format!("collections.{}", c.0),
+                message: "Collection has no items.".to_string(),
+            });
+        }
+        for i in &c.1.items {
+            keys.entry(i.0).or_default().push(c.0);
+            values.entry(i.1.value()).or_default().push(c.0);
+            if looks_like_path(i.1.value()) && !expand_tilde(i.1.value()).exists() {
+                problems.push(Problem {
+                    severity: Severity::Warning,
+                    check: "missing-path",
+                    location: format!("collections.{}.{}", c.0, i.0),
+                    message: format!("Path \"{}\" does not exist.", i.1.value()),
+                });
+            }
+        }
+    }
+    for (name, duplicates) in [("key", keys), ("value", values)] {
+        for (item, collections) in duplicates.iter().filter(|d| d.1.len() > 1) {
+            problems.push(Problem {
+                severity: Severity::Warning,
+                check: "duplicate",
+                location: format!("collections.{}", collections[0]),
+                message: format!(
+                    "The {} \"{}\" is in collections {}.",
+                    name,
+                    item,
+                    collections.iter().map(|c| format!("\"{}\"", c)).collect::<Vec<_>>().join(", ")
+                ),
+            });
+        }
+    }
+
+    problems
+}
+
+/// Checks that the program of a shell command can be found
+fn check_command(problems: &mut Vec<Problem>, location: String, command: &str) {
+    // Skip leading variable assignments such as `FOO=bar program`
+    let Some(program) = command.split_whitespace().find(|w| !is_assignment(w)) else {
+        return;
+    };
+    // Programs coming from variables are only known at run time
+    if program.contains('$') || SHELL_BUILTINS.contains(&program) {
+        return;
+    }
+    if !is_program(program) {
+        problems.push(Problem {
+            severity: Severity::Error,
+            check: "missing-command",
+            location,
+            message: format!("Command \"{}\" is not on PATH.", program),
+        });
+    }
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// Values starting like a path are expected to exist
+fn looks_like_path(value: &str) -> bool {
+    value == "~" || ["/", "~/", "./", "../"].iter().any(|p| value.starts_with(p))
+}
+
+/// Finds `$1`, `${1}`, `$@` or `$*` outside of double quotes.
+/// Inside single quotes, the shell does not expand them at all.
+fn has_unquoted_argument(runner: &str) -> bool {
+    let chars: Vec<char> = runner.chars().collect();
+    let (mut single, mut double) = (false, false);
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if !single => i += 1,
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '$' if !single && !double => {
+                let rest: String = chars[i + 1..].iter().collect();
+                if ["1", "{1}", "@", "*"].iter().any(|a| rest.starts_with(a)) {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
@@ -0,0 +1,232 @@
+use anyhow::*;
+use clap::ValueEnum;
+use serde::*;
+use std::{fs, path::*};
+
+use crate::{
+    collections::{Item, ItemEntry},
+    config::Config,
+};
+
+/// File formats for moving items in and out of qtai
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Csv,
+    Json,
+    Toml,
+}
+
+/// One item in an export, in the `collection,key,value,runner` schema
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub collection: String,
+    pub key: String,
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runner: Option<String>,
+}
+
+/// TOML needs a table at the top, so rows go in `[[items]]`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct TomlRows {
+    #[serde(default)]
+    items: Vec<Row>,
+}
+
+impl FileFormat {
+    /// Guesses the format from the file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "csv" => Some(FileFormat::Csv),
+            "json" => Some(FileFormat::Json),
+            "toml" => Some(FileFormat::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn write(self, rows: Vec<Row>) -> Result<String> {
+        Ok(match self {
+            FileFormat::Csv => {
+                let mut res = String::from("collection,key,value,runner\n");
+                for r in rows {
+                    let fields = [&r.collection, &r.key, &r.value, r.runner.as_deref().unwrap_or("")];
+                    res += &fields.iter().map(|f| csv_quote(f)).collect::<Vec<_>>().join(",");
+                    res.push('\n');
+                }
+                res
+            }
+            FileFormat::Json => serde_json::to_string_pretty(&rows)? + "\n",
+            FileFormat::Toml => toml::to_string_pretty(&TomlRows { items: rows })?,
+        })
+    }
+
+    pub fn read(self, text: &str) -> Result<Vec<Row>> {
+        match self {
+            FileFormat::Csv => read_csv(text),
+            FileFormat::Json => serde_json::from_str(text).context("Invalid JSON, expected a list of items"),
+            FileFormat::Toml => Ok(toml::from_str::<TomlRows>(text)?.items),
+        }
+    }
+}
+
+impl Row {
+    /// Plain items stay plain unless there is a runner
+    pub fn item(&self) -> Item {
+        match &self.runner {
+            None => Item::Plain(self.value.clone()),
+            Some(r) => Item::from(ItemEntry {
+                value: self.value.clone(),
+                runner: Some(r.clone()),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// Prints or writes the items of collections. Items from sources are not exported.
+pub fn export(
+    collections_input: &[String],
+    selective: bool,
+    format: FileFormat,
+    output: Option<&Path>,
+    config: &Config,
+) -> Result<()> {
+    let rows: Vec<Row> = config
+        .filter_collections(collections_input, selective)
+        .into_iter()
+        .flat_map(|(name, c)| {
+            c.items.iter().map(move |(key, item)| Row {
+                collection: name.to_string(),
+                key: key.clone(),
+                value: item.value().to_string(),
+                runner: item.runner().map(|r| r.to_string()),
+            })
+        })
+        .collect();
+    let text = format.write(rows)?;
+    match output {
+        Some(path) => {
+            fs::write(path, text)?;
+            eprintln!("Exported to {}.", path.display());
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+/// Quotes a CSV field when it needs it
+fn csv_quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Reads CSV with a `collection,key,value,runner` header. The columns can be in any order,
+/// and the runner column is optional.
+fn read_csv(text: &str) -> Result<Vec<Row>> {
+    let mut records = parse_csv(text).into_iter();
+    let header = records.next().context("CSV file is empty")?;
+    let column = |name: &str| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+    let find = |name: &str| {
+        column(name).with_context(|| format!("CSV header is missing the \"{}\" column", name))
+    };
+    let (collection, key, value) = (find("collection")?, find("key")?, find("value")?);
+    let runner = column("runner");
+    records
+        .enumerate()
+        .filter(|r| !(r.1.len() == 1 && r.1[0].is_empty()))
+        .map(|(line, record)| {
+            let field = |i: usize| {
+                record
+                    .get(i)
+                    .cloned()
+                    .with_context(|| format!("CSV record {} has too few fields", line + 2))
+            };
+            Ok(Row {
+                collection: field(collection)?,
+                key: field(key)?,
+                value: field(value)?,
+                runner: runner
+                    .and_then(|i| record.get(i))
+                    .filter(|r| !r.is_empty())
+                    .cloned(),
+            })
+        })
+        .collect()
+}
+
+/// Splits CSV into records of fields, with `"` quoting and `""` for a quote inside quotes
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_can_be_quoted() {
+        let text = "a,\"b, c\",\"say \"\"hi\"\"\"\r\n\"two\nlines\",,x";
+        assert_eq!(
+            parse_csv(text),
+            [vec!["a", "b, c", "say \"hi\""], vec!["two\nlines", "", "x"]]
+        );
+        assert_eq!(parse_csv("a,b\n"), [vec!["a", "b"]]);
+        assert!(parse_csv("").is_empty());
+    }
+
+    #[test]
+    fn csv_columns_are_found_by_name() {
+        let rows = read_csv("Value,key,collection\nhttps://a.example,a,web\n\n").unwrap();
+        assert_eq!(
+            rows,
+            [Row {
+                collection: "web".to_string(),
+                key: "a".to_string(),
+                value: "https://a.example".to_string(),
+                runner: None,
+            }]
+        );
+        assert!(read_csv("collection,key\nweb,a\n").is_err());
+        assert!(read_csv("collection,key,value\nweb,a\n").is_err());
+    }
+
+    #[test]
+    fn written_csv_reads_back() {
+        let rows = vec![Row {
+            collection: "web".to_string(),
+            key: "a, \"b\"".to_string(),
+            value: "line\nbreak".to_string(),
+            runner: Some("echo".to_string()),
+        }];
+        let text = FileFormat::Csv.write(rows.clone()).unwrap();
+        assert_eq!(FileFormat::Csv.read(&text).unwrap(), rows);
+    }
+}
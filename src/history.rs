@@ -0,0 +1,158 @@
+use anyhow::*;
+use indexmap::IndexMap;
+use serde::*;
+use std::{
+    fs,
+    path::*,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{config::Config, config_edit::COMPLETION_MESSAGE, paths::write_atomic};
+
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+/// Order of items in qtai run and qtai terminal-run
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    /// By key
+    #[default]
+    Alphabetical,
+    /// Often and recently launched items first, then by key
+    Frecency,
+}
+
+impl Sort {
+    pub fn is_default(&self) -> bool {
+        *self == Sort::default()
+    }
+}
+
+/// Tuning of the frecency score
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Frecency {
+    /// Days for a launch to count half as much
+    pub half_life: u64,
+    /// Days without a launch before `qtai history prune` forgets an item
+    pub prune_after: u64,
+}
+
+impl Default for Frecency {
+    fn default() -> Self {
+        Self {
+            half_life: 7,
+            prune_after: 90,
+        }
+    }
+}
+
+impl Frecency {
+    pub fn is_default(&self) -> bool {
+        *self == Frecency::default()
+    }
+}
+
+/// Launches of one item
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Launches {
+    pub count: u64,
+    /// Unix time of the last launch
+    pub last: u64,
+    /// Score at the last launch. Every launch adds one, and the score halves every half life.
+    pub score: f64,
+}
+
+/// Launches of items, keyed by collection then key
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct History {
+    pub collections: IndexMap<String, IndexMap<String, Launches>>,
+}
+
+/// The history file, usually `~/.local/state/qtai/history.json`
+pub fn history_file() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|d| d.join("qtai").join("history.json"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl History {
+    /// Reads the history. A missing or broken file is an empty history.
+    pub fn load() -> Self {
+        history_file()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|t| serde_json::from_str(&t).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = history_file().context("State directory not found.")?;
+        write_atomic(&path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Counts a launch of an item
+    pub fn record(&mut self, collection: &str, key: &str, frecency: &Frecency) {
+        let now = now();
+        let launches = self
+            .collections
+            .entry(collection.to_string())
+            .or_default()
+            .entry(key.to_string())
+            .or_default();
+        launches.score = decayed(launches, now, frecency) + 1.0;
+        launches.count += 1;
+        launches.last = now;
+    }
+
+    /// Frecency score of an item right now, 0 for items never launched
+    pub fn score(&self, collection: &str, key: &str, frecency: &Frecency) -> f64 {
+        self.collections
+            .get(collection)
+            .and_then(|c| c.get(key))
+            .map(|l| decayed(l, now(), frecency))
+            .unwrap_or(0.0)
+    }
+}
+
+/// Score of a launch after it lost some of its weight with time
+fn decayed(launches: &Launches, now: u64, frecency: &Frecency) -> f64 {
+    let days = now.saturating_sub(launches.last) as f64 / SECONDS_PER_DAY;
+    launches.score * 0.5_f64.powf(days / frecency.half_life.max(1) as f64)
+}
+
+/// Forgets items not launched for a while and collections which are gone
+pub fn prune(older_than: Option<u64>, dry_run: bool, config: &Config) -> Result<()> {
+    let mut history = History::load();
+    let days = older_than.unwrap_or(config.frecency.prune_after);
+    let oldest = now().saturating_sub(days * SECONDS_PER_DAY as u64);
+    let mut removed = 0;
+    history.collections.retain(|collection, items| {
+        let before = items.len();
+        if config.collections.contains_key(collection) {
+            items.retain(|_, l| l.last >= oldest);
+        } else {
+            items.clear();
+        }
+        removed += before - items.len();
+        !items.is_empty()
+    });
+    println!(
+        "Forgetting {} items not launched in {} days or from removed collections.",
+        removed, days
+    );
+    if dry_run {
+        println!("Dry run, so no changes are written.");
+        return Ok(());
+    }
+    history.save()?;
+    println!("{}", COMPLETION_MESSAGE);
+    Ok(())
+}
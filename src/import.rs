@@ -0,0 +1,378 @@
+use anyhow::*;
+use clap::ValueEnum;
+use colored::Colorize;
+use indexmap::IndexMap;
+use std::{
+    fs::{self, write},
+    path::*,
+};
+use toml_edit::DocumentMut;
+
+use crate::{
+    collections::{is_reserved_key, numbered_key, parent_names, Item, ItemEntry},
+    config::Config,
+    config_edit::{collection_table, collection_table_or_insert, item_to_toml, COMPLETION_MESSAGE},
+    export::FileFormat,
+    markup::{attribute, decode_entities, next_tag, tag_name, text_until},
+};
+
+/// What to do when an imported key is already in its collection
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collision {
+    /// Keep the item which is already there
+    #[default]
+    Skip,
+    /// Replace the item which is already there
+    Overwrite,
+    /// Add a number to the imported key, e.g. `docs (2)`
+    Rename,
+}
+
+/// How imported items mix with the items already in a collection
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Conflict {
+    /// Add new items and update items with the same key, keeping their description, icon and tags
+    #[default]
+    Merge,
+    /// Make each imported collection hold exactly the imported items
+    Replace,
+    /// Only add new items, leaving items with the same key as they are
+    Skip,
+}
+
+/// A change to one item of a collection
+#[derive(Debug, Clone)]
+enum Change {
+    Add(Item),
+    Update(Item),
+    Remove,
+}
+
+/// A bookmark from a browser export
+#[derive(Debug, Clone)]
+struct Bookmark {
+    /// Folders from the outermost one
+    folders: Vec<String>,
+    title: String,
+    url: String,
+}
+
+/// Imports a Netscape bookmark file, the HTML format every browser exports.
+/// Folders become nested collections of `into`, unless `flatten` puts everything in `into`.
+pub fn import_bookmarks(
+    file: &Path,
+    into: &str,
+    flatten: bool,
+    collision: Collision,
+    config_path: &Path,
+    config: &Config,
+) -> Result<()> {
+    let html = fs::read_to_string(file)
+        .with_context(|| format!("Cannot read bookmark file {}", file.display()))?;
+    let bookmarks = parse_bookmarks(&html);
+    if bookmarks.is_empty() {
+        return Err(anyhow!("No bookmarks found in {}.", file.display()));
+    }
+
+    // In the format of collection, key, item
+    let mut planned: IndexMap<String, IndexMap<String, Item>> = IndexMap::new();
+    let mut skipped = 0;
+    for b in bookmarks {
+        let collection = if flatten || b.folders.is_empty() {
+            into.to_string()
+        } else {
+            // A slash would make a nested collection
+            let folders: Vec<String> = b.folders.iter().map(|f| f.replace('/', "-")).collect();
+            format!("{}/{}", into, folders.join("/"))
+        };
+        let mut key = if b.title.trim().is_empty() { b.url.clone() } else { b.title.trim().to_string() };
+        let existing = config.collections.get(&collection).map(|c| &c.items);
+        let items = planned.entry(collection.clone()).or_default();
+        let is_taken = |k: &str| items.contains_key(k) || existing.is_some_and(|e| e.contains_key(k));
+        // A key of a collection setting would break the collection, so it is always numbered
+        if is_reserved_key(&key) {
+            let new_key = numbered_key(&key, is_taken);
+            println!(
+                "Adding bookmark \"{}\" to \"{}\" as \"{}\", since \"{}\" is a collection setting.",
+                key, collection, new_key, key
+            );
+            key = new_key;
+        }
+        let key = match collision {
+            _ if !is_taken(&key) => key,
+            Collision::Skip => {
+                skipped += 1;
+                continue;
+            }
+            Collision::Overwrite => key,
+            Collision::Rename => numbered_key(&key, is_taken),
+        };
+        items.insert(key, Item::Plain(b.url));
+    }
+
+    let mut docs: IndexMap<PathBuf, DocumentMut> = IndexMap::new();
+    let mut imported = 0;
+    for (collection, items) in planned.iter().filter(|p| !p.1.is_empty()) {
+        let doc = load_doc(&mut docs, collection_path(collection, config_path, config))?;
+        let table = collection_table_or_insert(doc, collection)?;
+        for (key, item) in items {
+            table[key] = item_to_toml(item);
+            imported += 1;
+        }
+    }
+    for (path, doc) in &docs {
+        write(path, doc.to_string())?;
+    }
+
+    println!(
+        "Imported {} bookmarks into {} collections, skipped {} which were already there.",
+        imported,
+        planned.values().filter(|i| !i.is_empty()).count(),
+        skipped
+    );
+    println!("{}", COMPLETION_MESSAGE);
+    Ok(())
+}
+
+/// Imports items in the `collection,key,value,runner` schema from CSV, JSON or TOML.
+/// The changes are always shown, and with dry run nothing is written.
+pub fn import_items(
+    file: &Path,
+    format: Option<FileFormat>,
+    conflict: Conflict,
+    dry_run: bool,
+    config_path: &Path,
+    config: &Config,
+) -> Result<()> {
+    let format = format
+        .or_else(|| FileFormat::from_path(file))
+        .context("Cannot tell the format from the file name. Use --format.")?;
+    let text = fs::read_to_string(file)
+        .with_context(|| format!("Cannot read {}", file.display()))?;
+    let rows = format
+        .read(&text)
+        .with_context(|| format!("Cannot read items from {}", file.display()))?;
+
+    // Later rows with the same collection and key win
+    let mut imported: IndexMap<String, IndexMap<String, Item>> = IndexMap::new();
+    for row in rows {
+        if is_reserved_key(&row.key) {
+            return Err(anyhow!(
+                "Key \"{}\" in collection \"{}\" is a collection setting and cannot name an item.",
+                row.key,
+                row.collection
+            ));
+        }
+        let item = row.item();
+        imported.entry(row.collection).or_default().insert(row.key, item);
+    }
+
+    // In the format of collection, key, change
+    let mut changes: Vec<(&str, &str, Change)> = Vec::new();
+    let mut skipped = 0;
+    for (collection, items) in &imported {
+        let existing = config.collections.get(collection).map(|c| &c.items);
+        for (key, item) in items {
+            match existing.and_then(|e| e.get(key)) {
+                None => changes.push((collection, key, Change::Add(item.clone()))),
+                Some(old) if old == item => {}
+                Some(_) if conflict == Conflict::Skip => skipped += 1,
+                Some(old) => {
+                    let new = match conflict {
+                        Conflict::Merge => merged(old, item),
+                        _ => item.clone(),
+                    };
+                    if &new != old {
+                        changes.push((collection, key, Change::Update(new)));
+                    }
+                }
+            }
+        }
+        if conflict == Conflict::Replace
+            && let Some(existing) = existing
+        {
+            changes.extend(
+                existing
+                    .keys()
+                    .filter(|k| !items.contains_key(*k))
+                    .map(|k| (collection.as_str(), k.as_str(), Change::Remove)),
+            );
+        }
+    }
+
+    let count = |f: fn(&Change) -> bool| changes.iter().filter(|c| f(&c.2)).count();
+    for (collection, key, change) in &changes {
+        let line = match change {
+            Change::Add(i) => format!("+ {}: \"{}\": \"{}\"", collection, key, i.value()).green(),
+            Change::Update(i) => format!("~ {}: \"{}\": \"{}\"", collection, key, i.value()).yellow(),
+            Change::Remove => format!("- {}: \"{}\"", collection, key).red(),
+        };
+        println!("{}", line);
+    }
+    println!(
+        "{} added, {} updated, {} removed, {} skipped.",
+        count(|c| matches!(c, Change::Add(_))),
+        count(|c| matches!(c, Change::Update(_))),
+        count(|c| matches!(c, Change::Remove)),
+        skipped
+    );
+    if dry_run {
+        println!("Dry run, so no changes are written.");
+        return Ok(());
+    }
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let mut docs: IndexMap<PathBuf, DocumentMut> = IndexMap::new();
+    for (collection, key, change) in changes {
+        // Items which are already there are changed in the file which has them
+        let path = match change {
+            Change::Add(_) => collection_path(collection, config_path, config),
+            Change::Update(_) | Change::Remove => config.item_file(collection, key, config_path),
+        };
+        let doc = load_doc(&mut docs, path)?;
+        match change {
+            Change::Add(i) | Change::Update(i) => {
+                collection_table_or_insert(doc, collection)?[key] = item_to_toml(&i);
+            }
+            Change::Remove => {
+                collection_table(doc, collection)?
+                    .as_table_like_mut()
+                    .context("Trouble converting collection as a table")?
+                    .remove(key);
+            }
+        }
+    }
+    for (path, doc) in &docs {
+        write(path, doc.to_string())?;
+    }
+    println!("{}", COMPLETION_MESSAGE);
+    Ok(())
+}
+
+/// Updates the value and runner of an item, keeping the rest
+fn merged(old: &Item, new: &Item) -> Item {
+    match old {
+        Item::Plain(_) => new.clone(),
+        Item::Detailed(e) => Item::from(ItemEntry {
+            value: new.value().to_string(),
+            runner: new.runner().or(e.runner.as_deref()).map(|r| r.to_string()),
+            ..(**e).clone()
+        }),
+    }
+}
+
+/// The file which holds a collection. New collections go to the file
+/// of their closest existing parent, or the root config.
+fn collection_path<'a>(collection: &str, config_path: &'a Path, config: &'a Config) -> &'a Path {
+    let known = std::iter::once(collection)
+        .chain(parent_names(collection))
+        .find(|c| config.collections.contains_key(*c));
+    match known {
+        Some(c) => config.collection_file(c, config_path),
+        None => config_path,
+    }
+}
+
+/// Loads a document once, so several changes to it are written together
+fn load_doc<'a>(docs: &'a mut IndexMap<PathBuf, DocumentMut>, path: &Path) -> Result<&'a mut DocumentMut> {
+    if !docs.contains_key(path) {
+        let doc = fs::read_to_string(path)?
+            .parse::<DocumentMut>()
+            .with_context(|| format!("Cannot parse {}", path.display()))?;
+        docs.insert(path.to_path_buf(), doc);
+    }
+    Ok(&mut docs[path])
+}
+
+/// Reads bookmarks from Netscape bookmark HTML.
+/// Folders are `<H3>` headings followed by a `<DL>` list, and bookmarks are `<A HREF>` links.
+fn parse_bookmarks(html: &str) -> Vec<Bookmark> {
+    let mut bookmarks = Vec::new();
+    // The root list has no folder
+    let mut folders: Vec<Option<String>> = Vec::new();
+    let mut heading: Option<String> = None;
+    let mut rest = html;
+    while let Some(tag) = next_tag(&mut rest) {
+        match tag_name(tag).as_str() {
+            "h3" => heading = Some(decode_entities(&text_until(&mut rest, "</h3>"))),
+            "dl" => folders.push(heading.take()),
+            "/dl" => {
+                folders.pop();
+            }
+            "a" => {
+                let title = decode_entities(&text_until(&mut rest, "</a>"));
+                if let Some(url) = attribute(tag, "href") {
+                    bookmarks.push(Bookmark {
+                        folders: folders.iter().flatten().cloned().collect(),
+                        title,
+                        url: decode_entities(&url),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    bookmarks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shortened from a Firefox export
+    const EXPORT: &str = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<!-- This is an automatically generated file.
+     It will be read and overwritten.
+     DO NOT EDIT! -->
+<META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=UTF-8">
+<meta http-equiv="Content-Security-Policy"
+      content="default-src 'self'; script-src 'none'; img-src data: *; object-src 'none'"></meta>
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks Menu</H1>
+
+<DL><p>
+    <DT><A HREF="https://www.mozilla.org/en-US/firefox/" ADD_DATE="1700000000" LAST_MODIFIED="1700000001" ICON_URI="https://www.mozilla.org/favicon.ico">Firefox</A>
+    <HR>
+    <DT><H3 ADD_DATE="1700000000" LAST_MODIFIED="1700000002" PERSONAL_TOOLBAR_FOLDER="true">Bookmarks Toolbar</H3>
+    <DL><p>
+        <DT><A HREF="https://github.com/search?q=qtai&amp;type=code" ADD_DATE="1700000003">Code &amp; Search</A>
+        <DT><H3 ADD_DATE="1700000004">Docs / Rust</H3>
+        <DL><p>
+            <DT><A HREF="https://doc.rust-lang.org/std/" ADD_DATE="1700000005">std</A>
+            <DD>The standard library
+        </DL><p>
+        <DT><A HREF="https://example.com/untitled" ADD_DATE="1700000006"></A>
+    </DL><p>
+    <DT><A HREF="place:sort=8&amp;maxResults=10">Recent Tags</A>
+</DL>
+"#;
+
+    #[test]
+    fn bookmarks_keep_their_folders() {
+        let bookmarks: Vec<(Vec<String>, String, String)> = parse_bookmarks(EXPORT)
+            .into_iter()
+            .map(|b| (b.folders, b.title, b.url))
+            .collect();
+        let folders = |f: &[&str]| f.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            bookmarks,
+            [
+                (folders(&[]), "Firefox".to_string(), "https://www.mozilla.org/en-US/firefox/".to_string()),
+                (
+                    folders(&["Bookmarks Toolbar"]),
+                    "Code & Search".to_string(),
+                    "https://github.com/search?q=qtai&type=code".to_string()
+                ),
+                (
+                    folders(&["Bookmarks Toolbar", "Docs / Rust"]),
+                    "std".to_string(),
+                    "https://doc.rust-lang.org/std/".to_string()
+                ),
+                (folders(&["Bookmarks Toolbar"]), "".to_string(), "https://example.com/untitled".to_string()),
+                (folders(&[]), "Recent Tags".to_string(), "place:sort=8&maxResults=10".to_string()),
+            ]
+        );
+    }
+}
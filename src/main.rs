@@ -1,8 +1,18 @@
+pub mod cache;
 pub mod collections;
 
 pub mod config;
 pub mod config_edit;
+pub mod doctor;
+pub mod export;
+pub mod history;
+pub mod import;
+pub mod markup;
+pub mod migrate;
+pub mod paths;
 pub mod run;
+pub mod sources;
+pub mod template;
 
 use anyhow::*;
 use clap::*;
@@ -12,8 +22,12 @@ use figment::providers::*;
 use figment::*;
 use std::path::*;
 
-use crate::config::Config;
+use crate::collections::{Item, ItemEntry};
+use crate::config::{CONFIG_VERSION, Config};
 use crate::config_edit::*;
+use crate::export::*;
+use crate::import::*;
+use crate::migrate::*;
 
 /// Store the CLI subcommand
 #[derive(Parser)]
@@ -25,6 +39,12 @@ struct Cli {
     assume_yes: bool,
     #[arg(short = 'c', long, help = "Custom config path")]
     config: Option<PathBuf>,
+    #[arg(
+        long = "set",
+        value_name = "KEY=VALUE",
+        help = "Override a config value, e.g. --set default_menu=\"fuzzel -d\""
+    )]
+    overrides: Vec<String>,
 }
 
 /// Store various CLI subcommands
@@ -32,7 +52,7 @@ struct Cli {
 enum Subcommands {
     #[command(alias = "r", about = "Run dmenu application")]
     Run {
-        #[arg(short = 'd', long, help = "Dmenu application. Default is \"dmenu\".")]
+        #[arg(short = 'd', long, help = "Dmenu application, or name of a menu from [menus]. Default is the collection menu or default menu.")]
         dmenu: Option<String>,
         #[arg(
             short = 's',
@@ -40,14 +60,20 @@ enum Subcommands {
             help = "Make collection more selective, only filtering collections with the exact same name"
         )]
         selective: bool,
-        #[arg(short = 'r', long, help = "Command to run from item.")]
+        #[arg(short = 'r', long, help = "Command to run from item, or @name of a named runner.")]
         runner: Option<String>,
+        #[arg(short = 'm', long, help = "Run every line the menu prints, for menus like rofi -multi-select or fzf -m")]
+        multi: bool,
+        #[arg(short = 't', long, help = "Pick a collection first, then one of its items")]
+        two_stage: bool,
+        #[arg(short = 'a', long, help = "Pick one of the actions of the item to run it with")]
+        actions: bool,
         #[arg(help = "Collections to input")]
         collection_input: Vec<String>,
     },
     #[command(alias = "t", about = "Run within terminal")]
     TerminalRun {
-        #[arg(short = 'r', long, help = "Command to run from item.")]
+        #[arg(short = 'r', long, help = "Command to run from item, or @name of a named runner.")]
         runner: Option<String>,
         #[arg(
             short = 's',
@@ -55,6 +81,12 @@ enum Subcommands {
             help = "Make collection more selective, only filtering collections with the exact same name"
         )]
         selective: bool,
+        #[arg(short = 'm', long, help = "Pick several items")]
+        multi: bool,
+        #[arg(short = 't', long, help = "Pick a collection first, then one of its items")]
+        two_stage: bool,
+        #[arg(short = 'a', long, help = "Pick one of the actions of the item to run it with")]
+        actions: bool,
         #[arg(help = "Collections to input")]
         collection_input: Vec<String>,
     },
@@ -66,6 +98,14 @@ enum Subcommands {
         value: String,
         #[arg(help = "Determine what collection to edit.")]
         collection_query: String,
+        #[arg(short = 'r', long, help = "Runner for this item only. Can be @name of a named runner.")]
+        runner: Option<String>,
+        #[arg(short = 'D', long, help = "Description shown next to the key.")]
+        description: Option<String>,
+        #[arg(short = 'i', long, help = "Icon name for menus which support icons.")]
+        icon: Option<String>,
+        #[arg(short = 't', long = "tag", help = "Tag to search by. Can be repeated.")]
+        tags: Vec<String>,
     },
     #[command(alias = "ri", about = "Removes an item from a config.")]
     RemoveItem { query: String },
@@ -73,6 +113,8 @@ enum Subcommands {
     AddCollection {
         #[arg(help = "Collection name to add.")]
         name: String,
+        #[arg(short = 'e', long, help = "Collection to inherit the runner, menu and items from.")]
+        extends: Option<String>,
     },
     #[command(alias = "rc", about = "Remove a collection from the config.")]
     RemoveCollection { query: String },
@@ -86,11 +128,14 @@ enum Subcommands {
             help = "Make collection more selective, only filtering collections with the exact same name"
         )]
         selective: bool,
+        #[arg(long, help = "Show where each setting came from (file, environment variable or CLI flag)")]
+        sources: bool,
     },
     #[command(alias = "s", about = "Search an item from a config.")]
     Search { query: String },
     #[command(alias = "cr", about = "Alter the default runner.")]
     ChangeRunner {
+        #[arg(help = "New runner command, or @name of a named runner.")]
         new_runner: String,
         #[arg(
             short = 'q',
@@ -100,10 +145,117 @@ enum Subcommands {
     },
 
     #[command(alias = "cm", about = "Alter the default menu for qtai run.")]
-    ChangeMenu { new_menu: String },
+    ChangeMenu {
+        #[arg(help = "New menu command, or name of a menu from [menus].")]
+        new_menu: String,
+        #[arg(
+            short = 'q',
+            help = "Collection to change menu. Default is changing global menu."
+        )]
+        collection_query: Option<String>,
+    },
 
     #[command(alias = "gcf", about = "Generates config file.")]
     GenerateConfigFile,
+
+    #[command(about = "Checks the config for problems without running anything.")]
+    Doctor {
+        #[arg(long, help = "Print the result as JSON")]
+        json: bool,
+        #[arg(long, help = "Fail on warnings too")]
+        strict: bool,
+    },
+
+    #[command(
+        about = "Imports items from a CSV, JSON or TOML file, or from other programs.",
+        args_conflicts_with_subcommands = true
+    )]
+    Import {
+        #[command(subcommand)]
+        kind: Option<ImportKind>,
+        #[arg(help = "File with collection, key, value and runner of each item.")]
+        file: Option<PathBuf>,
+        #[arg(short = 'f', long, value_enum, help = "Format of the file. Default is from the file extension.")]
+        format: Option<FileFormat>,
+        #[arg(long, value_enum, default_value_t, help = "What to do with keys which are already in the collection.")]
+        on_conflict: Conflict,
+        #[arg(short = 'n', long, help = "Show the changes without writing them")]
+        dry_run: bool,
+    },
+
+    #[command(about = "Exports items as CSV, JSON or TOML.")]
+    Export {
+        #[arg(help = "Collections to export. Default is all.")]
+        collections: Vec<String>,
+        #[arg(
+            short = 's',
+            long,
+            help = "Make collection more selective, only filtering collections with the exact same name"
+        )]
+        selective: bool,
+        #[arg(short = 'f', long, value_enum, default_value = "toml", help = "Format to export in.")]
+        format: FileFormat,
+        #[arg(short = 'o', long, help = "File to write to. Default is printing.")]
+        output: Option<PathBuf>,
+    },
+
+    #[command(about = "Shows or clears the cache of generated items.")]
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+
+    #[command(about = "Manages the history of launched items used for frecency.")]
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+
+    #[command(about = "Upgrades the config file to the current version, keeping comments.")]
+    Migrate {
+        #[arg(short = 'n', long, help = "Show the changes without writing them")]
+        dry_run: bool,
+    },
+}
+
+/// Store what can be imported
+#[derive(Subcommand, PartialEq)]
+enum ImportKind {
+    #[command(about = "Imports a bookmark HTML file exported from a browser.")]
+    Bookmarks {
+        #[arg(help = "Bookmark file in the Netscape HTML format.")]
+        file: PathBuf,
+        #[arg(long, default_value = "bookmarks", help = "Collection to import into. Folders become collections inside it.")]
+        into: String,
+        #[arg(long, help = "Put every bookmark straight into the collection, ignoring folders.")]
+        flatten: bool,
+        #[arg(long, value_enum, default_value_t, help = "What to do with keys which are already in the collection.")]
+        on_collision: Collision,
+    },
+}
+
+/// Store what can be done with the cache
+#[derive(Subcommand, PartialEq)]
+enum CacheAction {
+    #[command(about = "Lists cached collections and how old they are.")]
+    Status,
+    #[command(about = "Removes cached items.")]
+    Clear {
+        #[arg(help = "Collections to clear. Default is all.")]
+        collections: Vec<String>,
+    },
+}
+
+/// Store what can be done with the history
+#[derive(Subcommand, PartialEq)]
+enum HistoryAction {
+    #[command(about = "Forgets items which were not launched for a while or whose collection is gone.")]
+    Prune {
+        #[arg(long, value_name = "DAYS", help = "Forget items not launched in this many days. Default is frecency.prune_after.")]
+        older_than: Option<u64>,
+        #[arg(short = 'n', long, help = "Show what would be forgotten without writing it")]
+        dry_run: bool,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
@@ -114,68 +266,174 @@ fn main() -> anyhow::Result<()> {
             .join("qtai")
             .join("qtai.toml"),
     );
-    let config = determine_config(&config_path, &cli.subcommand, cli.assume_yes)?;
+    // Migration works on the raw files, since an old config might not load
+    if let Subcommands::Migrate { dry_run } = cli.subcommand {
+        return migrate(&config_path, dry_run, cli.assume_yes);
+    }
+    let config = determine_config(&config_path, &cli.overrides, &cli.subcommand, cli.assume_yes)?;
 
     // Run subcommand
-    match cli.subcommand {
+    let result = match cli.subcommand {
+        Subcommands::Cache {
+            action: CacheAction::Status,
+        } => crate::cache::status(&config),
+        Subcommands::Cache {
+            action: CacheAction::Clear { collections },
+        } => crate::cache::clear(&collections),
+        Subcommands::History {
+            action: HistoryAction::Prune { older_than, dry_run },
+        } => crate::history::prune(older_than, dry_run, &config),
         Subcommands::Run {
             collection_input,
             runner,
             dmenu,
             selective,
+            multi,
+            two_stage,
+            actions,
         } => crate::run::run(
             dmenu,
             &collection_input,
             runner,
-            &config,
-            cli.assume_yes,
+            &Config {
+                two_stage: two_stage || config.two_stage,
+                ..config.resolved()?
+            },
             selective,
+            multi,
+            actions,
         ),
         Subcommands::TerminalRun {
             collection_input,
             runner,
             selective,
+            multi,
+            two_stage,
+            actions,
         } => crate::run::terminal_run(
             &collection_input,
             runner,
-            &config,
-            cli.assume_yes,
+            &Config {
+                two_stage: two_stage || config.two_stage,
+                ..config.resolved()?
+            },
             selective,
+            multi,
+            actions,
         ),
-        Subcommands::GenerateConfigFile => Ok(()),
+        Subcommands::GenerateConfigFile | Subcommands::Migrate { .. } => Ok(()),
         Subcommands::AddItem {
             collection_query,
             key,
             value,
-        } => add_item(collection_query, key, value, config_path, config),
+            runner,
+            description,
+            icon,
+            tags,
+        } => {
+            // Only use the inline table form when it is needed
+            let item = if runner.is_none() && description.is_none() && icon.is_none() && tags.is_empty() {
+                Item::Plain(value)
+            } else {
+                Item::from(ItemEntry {
+                    value,
+                    runner,
+                    description,
+                    icon,
+                    tags,
+                    ..Default::default()
+                })
+            };
+            add_item(collection_query, key, item, config_path, config)
+        }
         Subcommands::RemoveItem { query } => remove_item(&query, config_path, config),
-        Subcommands::AddCollection { name } => add_collection(name, config_path, config),
+        Subcommands::AddCollection { name, extends } => {
+            add_collection(name, extends, config_path, config)
+        }
         Subcommands::RemoveCollection { query } => {
             remove_collection(&query, config_path, config, cli.assume_yes)
         }
         Subcommands::List {
             collections,
             selective,
-        } => config.list_collections(&collections, selective),
-        Subcommands::Search { query } => config.search_items(&query),
+            sources,
+        } => config
+            .resolved()?
+            .with_source_items(&collections, selective)
+            .list_collections(&collections, selective, sources),
+        Subcommands::Search { query } => config
+            .resolved()?
+            .with_source_items(&[], false)
+            .search_items(&query),
         Subcommands::ChangeRunner {
             new_runner,
             collection_query,
         } => change_runner(&new_runner, collection_query, &config_path, &config),
-        Subcommands::ChangeMenu { new_menu } => change_menu(&new_menu, &config_path, config),
-    }
+        Subcommands::Doctor { json, strict } => crate::doctor::doctor(&config, json, strict),
+        Subcommands::ChangeMenu {
+            new_menu,
+            collection_query,
+        } => change_menu(&new_menu, collection_query, &config_path, &config),
+        Subcommands::Import {
+            kind:
+                Some(ImportKind::Bookmarks {
+                    file,
+                    into,
+                    flatten,
+                    on_collision,
+                }),
+            ..
+        } => import_bookmarks(&file, &into, flatten, on_collision, &config_path, &config),
+        Subcommands::Import {
+            kind: None,
+            file,
+            format,
+            on_conflict,
+            dry_run,
+        } => import_items(
+            &file.context("Give a file to import, or what to import like \"bookmarks\".")?,
+            format,
+            on_conflict,
+            dry_run,
+            &config_path,
+            &config,
+        ),
+        Subcommands::Export {
+            collections,
+            selective,
+            format,
+            output,
+        } => export(&collections, selective, format, output.as_deref(), &config),
+    };
+    crate::cache::finish_refreshes();
+    result
 }
 
 /// Try to find a config file. If generate config command or config file is not found,
 /// then make a basic config file template.
+/// Config files are overridden by `QTAI_` environment variables, which are overridden by `--set`.
 fn determine_config(
     config_path: &PathBuf,
+    overrides: &[String],
     subcommand: &Subcommands,
     assume_yes: bool,
 ) -> anyhow::Result<Config> {
-    let result = Figment::new()
-        .merge(Toml::file(config_path))
-        .extract::<Config>();
+    let files = Config::files(config_path)?;
+    let mut figment = files
+        .iter()
+        .fold(Figment::new(), |f, p| f.merge(Toml::file(p)))
+        .merge(Env::prefixed("QTAI_").split("__"));
+    for o in overrides {
+        let (key, text) = o
+            .split_once('=')
+            .with_context(|| format!("Override \"{}\" is not in the form KEY=VALUE", o))?;
+        // Values are read like environment variables, so `true` or `3` are not strings
+        let parsed: value::Value = text.parse().unwrap_or_else(|_| text.into());
+        figment = figment.merge(Serialized::default(key.trim(), parsed));
+    }
+    let result = figment
+        .extract::<Config>()
+        .map(|c| c.flattened().with_files(&files).with_sources(&figment));
     match result {
         anyhow::Result::Ok(c) => {
             if &Subcommands::GenerateConfigFile == subcommand {
@@ -194,7 +452,27 @@ fn determine_config(
                         Ok(Config::default())
                     },
                 )
+            } else if c.version > CONFIG_VERSION {
+                Err(anyhow!(
+                    "Config file is version {}, but this qtai only knows up to version {}. Try updating qtai.",
+                    c.version,
+                    CONFIG_VERSION
+                ))
+            } else if let Some(name) = c.slashed_names.first()
+                && !matches!(subcommand, Subcommands::Doctor { .. })
+            {
+                // Such a collection would be read as nested, so edits would go to the wrong table
+                Err(anyhow!(
+                    "Collection \"{}\" has a / in its name, which is only for nested collections. Run \"qtai migrate\" to rename it.",
+                    name
+                ))
             } else {
+                if c.version < CONFIG_VERSION {
+                    eprintln!(
+                        "{}",
+                        "Config file is from an older version of qtai. Run \"qtai migrate\" to update it.".yellow()
+                    );
+                }
                 Ok(c)
             }
         }
@@ -222,9 +500,19 @@ fn determine_config(
             } else {
                 // When there is a config file but Figment errors out, there is something wrong.
                 println!("{}", format!("{}", e).red());
-                anyhow::Result::Err(anyhow!(
-                    "Something went wrong from reading config file. Make sure to have default runner and default menu set on the top level."
-                ))
+                if file_version(config_path).is_some_and(|v| v < CONFIG_VERSION) {
+                    println!("The config file is from an older version of qtai. Try \"qtai migrate\".");
+                }
+                match &e.metadata {
+                    Some(m) => anyhow::Result::Err(anyhow!(
+                        "Cannot read \"{}\" from {}.",
+                        e.path.join("."),
+                        Config::describe_source(m, &e.path.join("."))
+                    )),
+                    None => anyhow::Result::Err(anyhow!(
+                        "Something went wrong from reading config file. Make sure to have default runner and default menu set on the top level."
+                    )),
+                }
             }
         }
     }
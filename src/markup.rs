@@ -0,0 +1,113 @@
+/// Moves past the next tag and returns what is inside it, e.g. `a href="..."` for `<a href="...">`
+pub fn next_tag<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let start = rest.find('<')?;
+    let end = rest[start..].find('>')? + start;
+    let tag = &rest[start + 1..end];
+    *rest = &rest[end + 1..];
+    Some(tag)
+}
+
+/// Lowercase name of a tag, e.g. `/dl` for `</DL>` or `mime:mime-type` for `<mime:mime-type/>`
+pub fn tag_name(tag: &str) -> String {
+    tag.split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .to_ascii_lowercase()
+}
+
+/// Takes the text up to a closing tag, ignoring case
+pub fn text_until(rest: &mut &str, closing: &str) -> String {
+    let end = rest
+        .match_indices('<')
+        .map(|m| m.0)
+        .find(|i| rest[*i..].get(..closing.len()).is_some_and(|t| t.eq_ignore_ascii_case(closing)))
+        .unwrap_or(rest.len());
+    let text = rest[..end].trim().to_string();
+    *rest = &rest[end..];
+    text
+}
+
+/// Finds an attribute like `HREF="..."` in a tag, ignoring case
+pub fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(found) = lower[from..].find(name).map(|f| f + from) {
+        from = found + name.len();
+        let is_start = found == 0 || lower[..found].ends_with(char::is_whitespace);
+        let after = lower[from..].trim_start();
+        if !is_start || !after.starts_with('=') {
+            continue;
+        }
+        let value = tag[tag.len() - after.len() + 1..].trim_start();
+        return Some(match value.chars().next() {
+            Some(q @ ('"' | '\'')) => value[1..].split(q).next().unwrap_or_default().to_string(),
+            _ => value.split_whitespace().next().unwrap_or_default().to_string(),
+        });
+    }
+    None
+}
+
+/// Decodes the entities browsers and XML files use
+pub fn decode_entities(text: &str) -> String {
+    let mut res = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        res += &rest[..start];
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => match entity.strip_prefix('#') {
+                    Some(n) => match n.strip_prefix(['x', 'X']) {
+                        Some(h) => u32::from_str_radix(h, 16).ok(),
+                        None => n.parse().ok(),
+                    }
+                    .and_then(char::from_u32),
+                    None => None,
+                },
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                res.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                res.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    res + rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_are_found_in_any_case_and_quoting() {
+        let tag = r#"A data-href="no" ADD_DATE=1700000000 HREF = 'https://a.example/?q="x"'"#;
+        assert_eq!(attribute(tag, "href").as_deref(), Some(r#"https://a.example/?q="x""#));
+        assert_eq!(attribute(tag, "add_date").as_deref(), Some("1700000000"));
+        assert_eq!(attribute(r#"bookmark href="""#, "href").as_deref(), Some(""));
+        assert_eq!(attribute(tag, "icon"), None);
+        // Only whole attribute names match
+        assert_eq!(attribute("a data-href=x", "href"), None);
+        assert_eq!(attribute("a href", "href"), None);
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(decode_entities("a &amp; b &lt;c&gt; &#39;d&#x27; &quot;"), "a & b <c> 'd' \"");
+        assert_eq!(decode_entities("&unknown; & &#xzz;"), "&unknown; & &#xzz;");
+    }
+}
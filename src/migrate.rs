@@ -0,0 +1,387 @@
+use anyhow::*;
+use colored::Colorize;
+use itertools::Itertools;
+use similar::TextDiff;
+use std::{
+    fs::{self, write},
+    path::*,
+};
+use toml_edit::{value, DocumentMut, Key, Table, TableLike};
+
+use crate::{
+    collections::{is_reserved_key, numbered_key, Collection},
+    config::{Config, CONFIG_VERSION},
+};
+
+/// A migration upgrades the documents of every config file by one version, since a change
+/// in one file can affect others. The root config comes first and is the only file holding the version.
+type Migration = fn(&mut [DocumentMut]) -> Result<()>;
+
+/// Migration at index n upgrades version n to version n + 1
+const MIGRATIONS: [Migration; 3] = [add_version, rename_slashed_collections, rename_clashing_items];
+
+/// Reads the version of a config file. Files without a version are from before versioning.
+pub fn file_version(config_path: &Path) -> Option<u32> {
+    let table = toml::from_str::<toml::Table>(&fs::read_to_string(config_path).ok()?).ok()?;
+    match table.get("version") {
+        Some(v) => v.as_integer().and_then(|v| u32::try_from(v).ok()),
+        None => Some(0),
+    }
+}
+
+/// Rewrites the config files to the current version, keeping comments.
+/// With dry run, only the diff is shown.
+pub fn migrate(config_path: &Path, dry_run: bool, assume_yes: bool) -> Result<()> {
+    let version = file_version(config_path).context("Cannot read the version of the config file.")?;
+    if version > CONFIG_VERSION {
+        return Err(anyhow!(
+            "Config file is version {}, but this qtai only knows up to version {}.",
+            version,
+            CONFIG_VERSION
+        ));
+    }
+    if version == CONFIG_VERSION {
+        println!("Config file is already at version {}.", CONFIG_VERSION);
+        return Ok(());
+    }
+
+    let files = Config::files(config_path)?;
+    let mut olds = Vec::new();
+    let mut docs = Vec::new();
+    for file in &files {
+        let old = fs::read_to_string(file)?;
+        docs.push(
+            old.parse::<DocumentMut>()
+                .with_context(|| format!("Cannot parse {}", file.display()))?,
+        );
+        olds.push(old);
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut docs)?;
+    }
+    let mut changes: Vec<(PathBuf, String)> = Vec::new();
+    for ((file, old), doc) in files.into_iter().zip(olds).zip(docs) {
+        let new = doc.to_string();
+        if new != old {
+            print_diff(&file, &old, &new);
+            changes.push((file, new));
+        }
+    }
+
+    println!("Migrating from version {} to {}.", version, CONFIG_VERSION);
+    if dry_run {
+        println!("Dry run, so no changes are written.");
+        return Ok(());
+    }
+    crate::with_confirmation(
+        assume_yes,
+        "Write these changes?",
+        Some(true),
+        || {
+            for (file, new) in &changes {
+                write(file, new)?;
+            }
+            println!("Done (^-^)b");
+            Ok(())
+        },
+        || Err(anyhow!("User changed their mind.")),
+    )
+}
+
+/// Prints a unified diff with colors
+fn print_diff(file: &Path, old: &str, new: &str) {
+    let name = file.display().to_string();
+    let diff = TextDiff::from_lines(old, new);
+    for line in diff.unified_diff().header(&name, &name).to_string().lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("{}", line.bold());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Version 0 to 1: the version key goes first in the root config.
+/// Collections only had `default_runner` as a setting then, so keys of later settings are items.
+fn add_version(docs: &mut [DocumentMut]) -> Result<()> {
+    for doc in docs.iter_mut() {
+        rename_reserved_items(doc, &|key, _| key != "default_runner")?;
+    }
+    let table = docs[0].as_table_mut();
+    let keys: Vec<String> = table
+        .iter()
+        .filter(|i| !i.1.is_table() && i.0 != "version")
+        .map(|i| i.0.to_string())
+        .collect();
+    let values: Vec<(Key, toml_edit::Item)> = keys
+        .iter()
+        .filter_map(|k| table.remove_entry(k))
+        .collect();
+    table.insert("version", value(1));
+    for (key, item) in values {
+        table.insert_formatted(&key, item);
+    }
+    Ok(())
+}
+
+/// Version 1 to 2: a `/` in a collection name now marks a nested collection,
+/// so collections with one in their own name get a `-` instead. `extends` in every file follows the new names.
+fn rename_slashed_collections(docs: &mut [DocumentMut]) -> Result<()> {
+    // In the format of old name, new name
+    let mut renames: Vec<(String, String)> = Vec::new();
+    for doc in docs.iter_mut() {
+        if let Some(collections) = doc.get_mut("collections").and_then(|c| c.as_table_like_mut()) {
+            rename_slashed(collections, "", "", &mut renames)?;
+        }
+    }
+    // A collection spread over several files is renamed in each of them
+    let renames: Vec<(String, String)> = renames.into_iter().unique().collect();
+    for (old, new) in &renames {
+        println!("Renaming collection \"{}\" to \"{}\".", old, new);
+    }
+    for doc in docs.iter_mut() {
+        if let Some(collections) = doc.get_mut("collections").and_then(|c| c.as_table_like_mut()) {
+            rename_extends(collections, &renames);
+        }
+    }
+    docs[0]["version"] = value(2);
+    Ok(())
+}
+/// Renames collections under a `collections` table, whose parent is at the old and new path
+fn rename_slashed(
+    collections: &mut dyn TableLike,
+    old_parent: &str,
+    new_parent: &str,
+    renames: &mut Vec<(String, String)>,
+) -> Result<()> {
+    let path = |parent: &str, name: &str| match parent {
+        "" => name.to_string(),
+        p => format!("{}/{}", p, name),
+    };
+    let keys: Vec<String> = collections.iter().map(|i| i.0.to_string()).collect();
+    for key in keys {
+        let new_key = key.replace('/', "-");
+        if new_key != key {
+            if collections.contains_key(&new_key) {
+                return Err(anyhow!(
+                    "Cannot rename collection \"{}\" to \"{}\", since that name is taken.",
+                    path(old_parent, &key),
+                    path(new_parent, &new_key)
+                ));
+            }
+            let item = collections.remove(&key).context("Collection disappeared while renaming")?;
+            collections.insert(&new_key, item);
+            renames.push((path(old_parent, &key), path(new_parent, &new_key)));
+        }
+        if let Some(children) = collections
+            .get_mut(&new_key)
+            .and_then(|c| c.get_mut("collections"))
+            .and_then(|c| c.as_table_like_mut())
+        {
+            rename_slashed(children, &path(old_parent, &key), &path(new_parent, &new_key), renames)?;
+        }
+    }
+    Ok(())
+}
+
+/// Points `extends` of every collection under a `collections` table to the new names
+fn rename_extends(collections: &mut dyn TableLike, renames: &[(String, String)]) {
+    for (_, collection) in collections.iter_mut() {
+        let Some(collection) = collection.as_table_like_mut() else {
+            continue;
+        };
+        // The closest renamed collection decides, so children of renamed collections follow too
+        if let Some(extends) = collection.get_mut("extends")
+            && let Some(name) = extends.as_str()
+            && let Some((old, new)) = renames
+                .iter()
+                .filter(|r| name == r.0 || name.starts_with(&format!("{}/", r.0)))
+                .max_by_key(|r| r.0.len())
+        {
+            *extends = value(format!("{}{}", new, &name[old.len()..]));
+        }
+        if let Some(children) = collection.get_mut("collections").and_then(|c| c.as_table_like_mut()) {
+            rename_extends(children, renames);
+        }
+    }
+}
+
+/// Version 2 to 3: collections gained settings such as `history` and `actions`. An item with the key
+/// of one, like `history = "about:history"`, is renamed when its value cannot be that setting.
+fn rename_clashing_items(docs: &mut [DocumentMut]) -> Result<()> {
+    for doc in docs.iter_mut() {
+        rename_reserved_items(doc, &|key, item| !reads_as_collection(key, item))?;
+    }
+    docs[0]["version"] = value(3);
+    Ok(())
+}
+
+/// Renames items of every collection whose key is a collection setting, like `docs (2)`,
+/// if `should_rename` says the value is an item
+fn rename_reserved_items(
+    doc: &mut DocumentMut,
+    should_rename: &dyn Fn(&str, &toml_edit::Item) -> bool,
+) -> Result<()> {
+    if let Some(collections) = doc.get_mut("collections").and_then(|c| c.as_table_like_mut()) {
+        rename_items(collections, "", should_rename)?;
+    }
+    Ok(())
+}
+
+/// rename_reserved_items() for the collections under a `collections` table, whose parent is at the path
+fn rename_items(
+    collections: &mut dyn TableLike,
+    parent: &str,
+    should_rename: &dyn Fn(&str, &toml_edit::Item) -> bool,
+) -> Result<()> {
+    for (name, collection) in collections.iter_mut() {
+        let Some(collection) = collection.as_table_like_mut() else {
+            continue;
+        };
+        let path = match parent {
+            "" => name.to_string(),
+            p => format!("{}/{}", p, name),
+        };
+        // Only values which are items can move, anything else is left for loading to report
+        let keys: Vec<String> = collection
+            .iter()
+            .filter(|i| is_reserved_key(i.0) && should_rename(i.0, i.1) && reads_as_collection("item", i.1))
+            .map(|i| i.0.to_string())
+            .collect();
+        for key in keys {
+            let new_key = numbered_key(&key, |k| collection.contains_key(k));
+            let item = collection.remove(&key).context("Item disappeared while renaming")?;
+            collection.insert(&new_key, item);
+            println!("Renaming item \"{}\" of collection \"{}\" to \"{}\".", key, path, new_key);
+        }
+        if let Some(children) = collection.get_mut("collections").and_then(|c| c.as_table_like_mut()) {
+            rename_items(children, &path, should_rename)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a collection with only this key and value can be read
+fn reads_as_collection(key: &str, item: &toml_edit::Item) -> bool {
+    let mut table = Table::new();
+    table.insert(key, item.clone());
+    toml::from_str::<Collection>(&DocumentMut::from(table).to_string()).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the migrations from a version over the files, the root first
+    fn migrated(files: &[&str], from: usize) -> Result<Vec<String>> {
+        let mut docs: Vec<DocumentMut> = files.iter().map(|f| f.parse().unwrap()).collect();
+        for migration in &MIGRATIONS[from..] {
+            migration(&mut docs)?;
+        }
+        Ok(docs.iter().map(|d| d.to_string()).collect())
+    }
+
+    #[test]
+    fn version_goes_first_and_comments_stay() {
+        let root = "# Runner\ndefault_runner = \"echo\"\ndefault_menu = \"dmenu\"\n[collections.web]\n\"docs\" = \"a\"\n";
+        let new = migrated(&[root], 0).unwrap();
+        assert!(new[0].starts_with(&format!("version = {}\n# Runner\ndefault_runner", CONFIG_VERSION)));
+        assert!(new[0].contains("[collections.web]\n\"docs\" = \"a\"\n"));
+    }
+
+    #[test]
+    fn slashed_collections_are_renamed_in_every_file() {
+        let root = r#"version = 1
+[collections."work/urls"]
+"jira" = "a"
+[collections."work/urls".collections."a/b"]
+"x" = "b"
+[collections.child]
+extends = "work/urls/a/b"
+"#;
+        let included = r#"[collections."work/urls"]
+"wiki" = "c"
+[collections.other]
+extends = "work/urls"
+[collections.kept]
+extends = "work/urlsx"
+"#;
+        let new = migrated(&[root, included], 1).unwrap();
+        assert!(new[0].contains("[collections.work-urls]\n\"jira\" = \"a\""));
+        assert!(new[0].contains("[collections.work-urls.collections.a-b]"));
+        assert!(new[0].contains("extends = \"work-urls/a-b\""));
+        assert!(new[1].contains("[collections.work-urls]\n\"wiki\" = \"c\""));
+        assert!(new[1].contains("extends = \"work-urls\""));
+        assert!(new[1].contains("extends = \"work/urlsx\""));
+    }
+
+    #[test]
+    fn slashed_renames_do_not_overwrite() {
+        let root = "version = 1\n[collections.\"a/b\"]\n\"x\" = \"1\"\n[collections.a-b]\n\"y\" = \"2\"\n";
+        assert!(migrated(&[root], 1).is_err());
+    }
+
+    #[test]
+    fn actions_items_are_renamed() {
+        let root = r#"version = 2
+default_runner = "echo"
+default_menu = "dmenu"
+[collections.github]
+"actions" = "https://github.com/org/repo/actions"
+"actions (2)" = "taken"
+[collections.github.collections.pr]
+actions = { value = "https://github.com/org/repo/pulls", tags = ["ci"] }
+[collections.kept]
+actions = { open = "xdg-open \"$1\"" }
+"#;
+        let new = migrated(&[root], 2).unwrap();
+        assert!(new[0].starts_with("version = 3\n"));
+        assert!(new[0].contains("\"actions (3)\" = \"https://github.com/org/repo/actions\""));
+        assert!(new[0].contains("\"actions (2)\" = { value = \"https://github.com/org/repo/pulls\", tags = [\"ci\"] }"));
+        assert!(new[0].contains("actions = { open = "));
+        let config: Config = toml::from_str(&new[0]).unwrap();
+        assert!(config.collections["kept"].actions.contains_key("open"));
+    }
+
+    #[test]
+    fn history_items_are_renamed() {
+        let root = r#"version = 2
+default_runner = "echo"
+default_menu = "dmenu"
+[collections.browser]
+"history" = "about:history"
+[collections.private]
+history = false
+"#;
+        let new = migrated(&[root], 2).unwrap();
+        assert!(new[0].contains("\"history (2)\" = \"about:history\""));
+        assert!(new[0].contains("history = false"));
+        let config: Config = toml::from_str(&new[0]).unwrap();
+        assert_eq!(config.collections["browser"].history, None);
+        assert_eq!(config.collections["private"].history, Some(false));
+    }
+
+    #[test]
+    fn description_items_of_unversioned_files_are_renamed() {
+        let root = r#"default_runner = "echo"
+default_menu = "dmenu"
+[collections.docs]
+default_runner = "xdg-open"
+"description" = "https://example.com/description"
+"menu" = "https://example.com/menu"
+"#;
+        let included = "[collections.more]\n\"description\" = \"https://example.com/more\"\n";
+        let new = migrated(&[root, included], 0).unwrap();
+        assert!(new[0].contains("default_runner = \"xdg-open\""));
+        assert!(new[0].contains("\"description (2)\" = \"https://example.com/description\""));
+        assert!(new[0].contains("\"menu (2)\" = \"https://example.com/menu\""));
+        assert!(new[1].contains("\"description (2)\" = \"https://example.com/more\""));
+        let config: Config = toml::from_str(&new[0]).unwrap();
+        assert_eq!(config.collections["docs"].description, None);
+        assert_eq!(config.collections["docs"].items.len(), 2);
+    }
+}
@@ -1,9 +1,76 @@
 use anyhow::*;
-use dialoguer::Select;
+use dialoguer::{Input, MultiSelect, Select};
 use indexmap::IndexMap;
+use itertools::Itertools;
+use std::io::Write;
 use std::process::{Command, Stdio};
 
+use crate::collections::{parent_name, Collection, Item, MultiRun};
 use crate::config::Config;
+use crate::history::{History, Sort};
+use crate::template::{self, Variables};
+
+/// An item in the format of collection, keypair
+type CollectionItem<'a> = (&'a str, (&'a str, &'a Item));
+
+/// A line in a menu
+#[derive(Debug, Clone, Copy)]
+pub enum Entry<'a> {
+    /// An item, in the format of collection, keypair
+    Item(&'a str, (&'a str, &'a Item)),
+    /// A nested collection to open
+    Group(&'a str),
+    /// A collection to open in the first stage of a two-stage run
+    Collection(&'a str, &'a Collection),
+    /// Another way to run the picked item, in the format of name, runner
+    Action(&'a str, &'a str),
+    /// Go back to the parent collection
+    Back,
+}
+
+/// What was picked from a menu
+pub enum Picked {
+    Entry(usize),
+    /// Text which is not one of the lines
+    Typed(String),
+    /// Several lines from a multi-select menu
+    Several(Vec<Picked>),
+    Cancelled,
+}
+
+/// How the parameters of an item, such as `{?query}`, are asked for
+#[derive(Debug, Clone, Copy)]
+pub enum Prompt<'a> {
+    /// Another invocation of the menu, listing the suggestions
+    Menu(&'a str),
+    /// A line of input in the terminal
+    Terminal,
+}
+
+impl Prompt<'_> {
+    /// Asks for the answer to a parameter, or None if nothing was given
+    fn ask(&self, parameter: &str, suggestions: &[String]) -> Result<Option<String>> {
+        let answer = match self {
+            Prompt::Menu(menu) => {
+                let lines = suggestions.iter().map(|s| s.to_string() + "\n").collect::<String>();
+                let output = menu_output(menu, &lines)?;
+                output.lines().next().unwrap_or("").to_string()
+            }
+            Prompt::Terminal => {
+                let prompt = match suggestions {
+                    [] => parameter.to_string(),
+                    _ => format!("{} (e.g. {})", parameter, suggestions.join(", ")),
+                };
+                Input::<String>::new()
+                    .with_prompt(prompt)
+                    .allow_empty(true)
+                    .interact_text()
+                    .context("Cannot observe user input")?
+            }
+        };
+        Ok(Some(answer).filter(|a| !a.is_empty()))
+    }
+}
 
 ///Run command into a dmenu and runs the output based on config
 pub fn run(
@@ -11,153 +78,532 @@ pub fn run(
     collections_input: &[String],
     runner: Option<String>,
     config: &Config,
-    _assume_yes: bool,
     selective: bool,
+    multi: bool,
+    actions: bool,
 ) -> Result<()> {
-    let menu = match menu_option {
+    let config = &config.clone().with_source_items(collections_input, selective);
+    let collections = config.filter_collections(collections_input, selective);
+    let menu_name = match &menu_option {
         Some(x) => x,
-        None => config.default_menu.clone(),
+        None => choose_menu(config, &collections),
     };
-    // In the format of collection, keypair
-    let items: Vec<(&str, (&str, &str))> = config
-        .filter_collections(collections_input, selective)
-        .iter()
-        .flat_map(|i| {
-            i.1.items
-                .iter()
-                .map(|j| (*i.0, (j.0.as_str(), j.1.as_str())))
+    let menu = config.resolve_menu(menu_name);
+    let icons = config.menu_icons(menu_name);
+    navigate(&collections, runner, config, Prompt::Menu(menu), actions, |entries, display| {
+        open_menu(menu, entries, display, multi, icons)
+    })
+}
+
+/// run() but in the terminal using dialoguer select
+pub fn terminal_run(
+    collections_input: &[String],
+    runner: Option<String>,
+    config: &Config,
+    selective: bool,
+    multi: bool,
+    actions: bool,
+) -> Result<()> {
+    let config = &config.clone().with_source_items(collections_input, selective);
+    let collections = config.filter_collections(collections_input, selective);
+    navigate(&collections, runner, config, Prompt::Terminal, actions, |_, display| {
+        if multi {
+            let selection = MultiSelect::new()
+                .with_prompt("What do you choose? (space to select, enter to run)")
+                .items(display)
+                .interact_opt()
+                .context("Cannot observe user input")?
+                .unwrap_or_default();
+            let mut picked: Vec<Picked> = selection.into_iter().map(Picked::Entry).collect();
+            return Ok(match picked.len() {
+                0 => Picked::Cancelled,
+                1 => picked.remove(0),
+                _ => Picked::Several(picked),
+            });
+        }
+        let selection = Select::new()
+            .with_prompt("What do you choose? (arrow or vi keys)")
+            .items(display)
+            .interact_opt()
+            .context("Cannot observe user input")?;
+        Ok(match selection {
+            Some(i) => Picked::Entry(i),
+            None => Picked::Cancelled,
         })
+    })
+}
+
+/// Shows the collections level by level with `pick` until an item is picked, then runs it.
+/// Nested collections show up as groups, which open in the same way with a back entry.
+/// In two stages, the collections are picked first and cancelling an item goes back to them.
+/// With actions, an item with actions opens an action menu, and cancelling it goes back to the items.
+fn navigate<'a>(
+    collections: &IndexMap<&'a str, &'a Collection>,
+    runner: Option<String>,
+    config: &Config,
+    prompt: Prompt,
+    actions: bool,
+    mut pick: impl FnMut(&[Entry<'a>], &[String]) -> Result<Picked>,
+) -> Result<()> {
+    // Collections whose parent is not shown are at the top level
+    let roots: Vec<&str> = collections
+        .keys()
+        .copied()
+        .filter(|c| !parent_name(c).is_some_and(|p| collections.contains_key(p)))
         .collect();
-    if items.len() == 0 {
-        return Err(anyhow!("No items are found"));
+    let mut history = History::load();
+    // Without frecency, items keep their alphabetical order
+    let ranking = (config.sort == Sort::Frecency).then_some(&history);
+    let two_stage = config.two_stage;
+    let mut level: Option<&str> = None;
+    // The item waiting for an action
+    let mut chosen: Option<CollectionItem> = None;
+    loop {
+        let entries = match (&chosen, level) {
+            (Some((c, (_, item))), _) => action_entries(collections[c], item),
+            (None, None) if two_stage => collections.iter().map(|c| Entry::Collection(c.0, c.1)).collect(),
+            (None, _) => level_entries(collections, &roots, level, ranking, config),
+        };
+        if entries.is_empty() {
+            return Err(anyhow!("No items are found"));
+        }
+        let display = display_entries(&entries);
+        match pick(&entries, &display)? {
+            Picked::Entry(i) => match entries[i] {
+                Entry::Item(collection, (key, item))
+                    if actions && !action_entries(collections[collection], item).is_empty() =>
+                {
+                    chosen = Some((collection, (key, item)));
+                }
+                // For a picked item, the input is its key rather than the decorated menu line
+                Entry::Item(collection, (key, item)) => {
+                    remember(&mut history, &[(collection, key)], collections, config);
+                    return run_command(
+                        (Some(collection), (Some(key), item)),
+                        key,
+                        runner,
+                        config,
+                        prompt,
+                    );
+                }
+                // The action replaces every other runner
+                Entry::Action(_, action) => {
+                    let Some((collection, (key, item))) = chosen.take() else {
+                        continue;
+                    };
+                    remember(&mut history, &[(collection, key)], collections, config);
+                    return run_command(
+                        (Some(collection), (Some(key), item)),
+                        key,
+                        Some(action.to_string()),
+                        config,
+                        prompt,
+                    );
+                }
+                Entry::Group(g) | Entry::Collection(g, _) => level = Some(g),
+                // Every collection is listed in the first stage, so any of them can be the parent
+                Entry::Back if two_stage => {
+                    level = level
+                        .and_then(parent_name)
+                        .filter(|p| collections.contains_key(p))
+                }
+                Entry::Back => {
+                    level = level
+                        .and_then(parent_name)
+                        .filter(|p| !roots.contains(p))
+                }
+            },
+            // Only a listed action can be run on the chosen item
+            Picked::Typed(_) | Picked::Several(_) | Picked::Cancelled if chosen.is_some() => chosen = None,
+            // Sometimes selected item is not in the list. It is run with the default runner.
+            Picked::Typed(text) => {
+                return run_command(
+                    (None, (None, &Item::Plain(text.clone()))),
+                    &text,
+                    runner,
+                    config,
+                    prompt,
+                );
+            }
+            // Groups, collections and the back entry do nothing when picked with other lines
+            Picked::Several(picked) => {
+                let mut items: Vec<CollectionItem> = Vec::new();
+                let mut typed: Vec<String> = Vec::new();
+                for p in picked {
+                    match p {
+                        Picked::Entry(i) => {
+                            if let Entry::Item(c, item) = entries[i] {
+                                items.push((c, item));
+                            }
+                        }
+                        Picked::Typed(text) => typed.push(text),
+                        _ => {}
+                    }
+                }
+                let launched: Vec<(&str, &str)> = items.iter().map(|i| (i.0, i.1.0)).collect();
+                remember(&mut history, &launched, collections, config);
+                run_several(&items, runner.clone(), config, prompt)?;
+                for text in typed {
+                    run_command(
+                        (None, (None, &Item::Plain(text.clone()))),
+                        &text,
+                        runner.clone(),
+                        config,
+                        prompt,
+                    )?;
+                }
+                return Ok(());
+            }
+            Picked::Cancelled if two_stage && level.is_some() => level = None,
+            Picked::Cancelled => return Ok(()),
+        }
+    }
+}
+
+/// Lists the actions of an item. Item actions override collection actions with the same name.
+fn action_entries<'a>(collection: &'a Collection, item: &'a Item) -> Vec<Entry<'a>> {
+    let mut actions: IndexMap<&str, &str> = collection
+        .actions
+        .iter()
+        .map(|a| (a.0.as_str(), a.1.as_str()))
+        .collect();
+    actions.extend(item.actions().into_iter().flatten().map(|a| (a.0.as_str(), a.1.as_str())));
+    actions.into_iter().map(|a| Entry::Action(a.0, a.1)).collect()
+}
+
+/// Records launches in the history, skipping collections which opted out
+fn remember(
+    history: &mut History,
+    launched: &[(&str, &str)],
+    collections: &IndexMap<&str, &Collection>,
+    config: &Config,
+) {
+    let mut changed = false;
+    for (collection, key) in launched {
+        if collections.get(collection).is_some_and(|c| c.history != Some(false)) {
+            history.record(collection, key, &config.frecency);
+            changed = true;
+        }
+    }
+    if changed && let Err(e) = history.save() {
+        eprintln!("Cannot save the history: {}", e);
+    }
+}
+
+/// Runs several items. Each collection decides with `multi` whether its items run
+/// one at a time, or together once with the values as `$1..$n`.
+fn run_several(
+    items: &[CollectionItem],
+    runner: Option<String>,
+    config: &Config,
+    prompt: Prompt,
+) -> Result<()> {
+    let by_collection = items.iter().into_group_map_by(|i| i.0);
+    for collection in items.iter().map(|i| i.0).unique() {
+        let items = &by_collection[collection];
+        let mode = config
+            .collections
+            .get(collection)
+            .and_then(|c| c.multi)
+            .unwrap_or_default();
+        match mode {
+            MultiRun::Each => {
+                for (c, (key, item)) in items.iter() {
+                    run_command((Some(c), (Some(key), item)), key, runner.clone(), config, prompt)?;
+                }
+            }
+            MultiRun::All => {
+                let values: Vec<&str> = items.iter().map(|i| i.1.1.value()).collect();
+                let (_, (key, first)) = items[0];
+                run_with_values(
+                    (Some(collection), (Some(key), first)),
+                    key,
+                    runner.clone(),
+                    config,
+                    prompt,
+                    &values,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lists the entries of a level. The top level shows the items of the top level collections.
+fn level_entries<'a>(
+    collections: &IndexMap<&'a str, &'a Collection>,
+    roots: &[&'a str],
+    level: Option<&str>,
+    ranking: Option<&History>,
+    config: &Config,
+) -> Vec<Entry<'a>> {
+    let shown: IndexMap<&str, &Collection> = match level {
+        Some(l) => collections.iter().filter(|c| *c.0 == l).map(|c| (*c.0, *c.1)).collect(),
+        None => roots.iter().map(|r| (*r, collections[r])).collect(),
     };
-    let items_display = display_pairs(&items);
+    let back = level.map(|_| Entry::Back);
+    let groups = collections
+        .keys()
+        .filter(|c| parent_name(c).is_some_and(|p| shown.contains_key(p)))
+        .map(|c| Entry::Group(c));
+    let items = collect_items(&shown, ranking, config)
+        .into_iter()
+        .map(|i| Entry::Item(i.0, i.1));
+    back.into_iter().chain(groups).chain(items).collect()
+}
+
+/// Formats entries as menu lines. Groups are shown by their own name,
+/// unless two groups share a name, in which case the full path is used.
+/// Collections are shown by their full path with their item count and description.
+pub fn display_entries(entries: &[Entry]) -> Vec<String> {
+    let items: Vec<(&str, (&str, &Item))> = entries
+        .iter()
+        .filter_map(|e| match e {
+            Entry::Item(c, i) => Some((*c, *i)),
+            _ => None,
+        })
+        .collect();
+    let mut items_display = display_pairs(&items).into_iter();
+    let group_name = |g: &'_ str| g.rsplit('/').next().unwrap_or(g).to_string();
+    let group_names: Vec<String> = entries
+        .iter()
+        .filter_map(|e| match e {
+            Entry::Group(g) => Some(group_name(g)),
+            _ => None,
+        })
+        .collect();
+    entries
+        .iter()
+        .map(|e| match e {
+            Entry::Item(..) => items_display.next().unwrap_or_default(),
+            Entry::Group(g) => {
+                let name = group_name(g);
+                if group_names.iter().filter(|n| **n == name).count() > 1 {
+                    format!("{}/", g)
+                } else {
+                    format!("{}/", name)
+                }
+            }
+            Entry::Collection(name, collection) => {
+                let count = collection.items.len();
+                let mut display = format!("{} ({} item{})", name, count, if count == 1 { "" } else { "s" });
+                if let Some(d) = &collection.description {
+                    display += &format!(" - {}", d);
+                }
+                display
+            }
+            Entry::Action(name, _) => name.to_string(),
+            Entry::Back => "..".to_string(),
+        })
+        .collect()
+}
 
-    let items_display_accumlated = items_display
+/// Writes the lines into the menu command and reads what was picked
+fn open_menu(menu: &str, entries: &[Entry], display: &[String], multi: bool, icons: bool) -> Result<Picked> {
+    // Icons use the extended dmenu protocol understood by rofi and fuzzel
+    let display_accumlated = display
         .iter()
-        .fold("".to_owned(), |acc, i| acc + i + "\n");
+        .zip(entries)
+        .fold("".to_owned(), |acc, (line, e)| match e {
+            Entry::Item(_, (_, item)) if icons && item.icon().is_some() => {
+                acc + line + "\0icon\x1f" + item.icon().unwrap() + "\n"
+            }
+            _ => acc + line + "\n",
+        });
+    let selection = menu_output(menu, &display_accumlated)?;
+    // We remove the trailing \n character
+    let selection = selection.strip_suffix('\n').unwrap_or(&selection);
 
-    let echo = Command::new("echo")
-        .arg(&items_display_accumlated)
-        .stdout(Stdio::piped())
-        .spawn()?;
-    let menu = Command::new("sh")
+    // Menus which print their input back may keep the icon data after a NUL
+    let to_picked = |line: &str| {
+        let line = line.split('\0').next().unwrap_or(line);
+        match display.iter().position(|i| i == line) {
+            Some(i) => Picked::Entry(i),
+            None => Picked::Typed(line.to_string()),
+        }
+    };
+    // Multi-select menus print one line for each selected line
+    let lines: Vec<&str> = match multi {
+        true => selection.lines().filter(|l| !l.is_empty()).collect(),
+        false => vec![selection].into_iter().filter(|l| !l.is_empty()).collect(),
+    };
+    Ok(match lines.as_slice() {
+        [] => Picked::Cancelled,
+        [line] => to_picked(line),
+        _ => Picked::Several(lines.into_iter().map(to_picked).collect()),
+    })
+}
+
+/// Writes the input into the menu command and returns what it printed
+fn menu_output(menu: &str, input: &str) -> Result<String> {
+    let mut menu = Command::new("sh")
         .arg("-c").arg(menu)
-        .stdin(Stdio::from(echo.stdout.unwrap()))
+        .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
         .context("Cannot run menu. Your menu application might not be installed or you might have messed up your flags.")?;
-    let selection =
-        String::from_utf8(menu.wait_with_output()?.stdout).context("Output is not utf8")?;
+    // Menus are allowed to ignore their input, so a broken pipe is not an error
+    let _ = menu
+        .stdin
+        .take()
+        .context("Cannot write to menu")?
+        .write_all(input.as_bytes());
+    String::from_utf8(menu.wait_with_output()?.stdout).context("Output is not utf8")
+}
 
-    // Indexmap probably be better here but I am too lazy to figure it out
-    // Also we remove the last character (it's a \n character)
-    let selected_item_index_option = items_display
+/// Picks the menu when none is given on the command line, as it is written in the config.
+/// Collections without a menu do not count. If the remaining collections agree on a menu,
+/// that menu is used. Otherwise, the default menu is used with a warning.
+fn choose_menu<'a>(config: &'a Config, collections: &IndexMap<&str, &'a Collection>) -> &'a str {
+    // In the format of resolved menu, (menu, collection)
+    let menus: IndexMap<&str, (&str, &str)> = collections
         .iter()
-        .position(|i| i == &selection[..selection.len() - 1])
-        ;
-    // Sometimes selected item is not in the list. This makes sure that is passed as None
-    let selected_item = match selected_item_index_option {
-        Some(x) => {
-            let extracted_item = items.get(x).unwrap();
-            (Some(extracted_item.0), (Some(extracted_item.1.0), extracted_item.1.1))},
-        None => (None, (None, selection.as_str())),
-    };
-    run_command(selected_item, runner, config)
+        .filter_map(|i| i.1.menu.as_deref().map(|m| (config.resolve_menu(m), (m, *i.0))))
+        .collect();
+    match menus.len() {
+        0 => &config.default_menu,
+        1 => menus.first().unwrap().1.0,
+        _ => {
+            eprintln!(
+                "Collections {} use different menus, so the default menu is used.",
+                menus.values().map(|c| format!("\"{}\"", c.1)).join(", ")
+            );
+            &config.default_menu
+        }
+    }
 }
 
-/// run() but in the terminal using dialoguer select
-pub fn terminal_run(
-    collections_input: &[String],
-    runner: Option<String>,
+/// Gathers items from the filtered collections, sorted by key.
+/// With a ranking, items with a higher frecency score come first.
+fn collect_items<'a>(
+    collections: &IndexMap<&'a str, &'a Collection>,
+    ranking: Option<&History>,
     config: &Config,
-    _assume_yes: bool,
-    selective: bool,
-) -> Result<()> {
-    // In the format of collection, keypair
-    let items: Vec<(&str, (&str, &str))> = config
-        .filter_collections(collections_input, selective)
+) -> Vec<(&'a str, (&'a str, &'a Item))> {
+    let mut items: Vec<(&str, (&str, &Item))> = collections
         .iter()
-        .flat_map(|i| {
-            i.1.items
-                .iter()
-                .map(|j| (*i.0, (j.0.as_str(), j.1.as_str())))
-        })
+        .flat_map(|i| i.1.items.iter().map(|j| (*i.0, (j.0.as_str(), j.1))))
         .collect();
-    if items.len() == 0 {
-        return Err(anyhow!("No items are found"));
+    items.sort_by_key(|i| i.1.0.to_lowercase());
+    if let Some(history) = ranking {
+        let score = |i: &(&str, (&str, &Item))| match collections[i.0].history {
+            Some(false) => 0.0,
+            _ => history.score(i.0, i.1.0, &config.frecency),
+        };
+        // The sort is stable, so items with the same score stay alphabetical
+        items.sort_by(|a, b| score(b).total_cmp(&score(a)));
     }
+    items
+}
 
-    let selection = Select::new()
-        .with_prompt("What do you choose? (arrow or vi keys)")
-        .items(display_pairs(&items))
-        .interact()
-        .context("Cannot observe user input")?;
-    let selected_item = items[selection];
-    run_command(
-        (
-            Some(selected_item.0),
-            (selected_item.1.0, selected_item.1.1),
-        ),
-        runner,
-        config,
-    )
-}
-
-/// Takes a vector pairs tupled with collection and convert them into a displayable format
-pub fn display_pairs(pairs: &[(&str, (&str, &str))]) -> Vec<String> {
+/// Takes a vector pairs tupled with collection and convert them into a displayable format.
+/// The output keeps the same order as the input.
+pub fn display_pairs(pairs: &[(&str, (&str, &Item))]) -> Vec<String> {
     // Check if values have duplicates
     let mut has_duplicate = IndexMap::new();
     for i in pairs {
-        let _ = *has_duplicate
+        has_duplicate
             .entry(i.1.0)
             .and_modify(|e| *e = true)
             .or_insert(false);
     }
 
     // Label each pair with a string
-    let mut res: Vec<String> = pairs
+    pairs
         .iter()
         .map(|i| {
             // (There shouldn't be any default values)
-            if *has_duplicate.entry(i.1.0).or_default() {
+            let mut display = if *has_duplicate.entry(i.1.0).or_default() {
                 format!("{} (from collection \"{}\")", i.1.0, i.0)
             } else {
                 i.1.0.to_owned()
+            };
+            if let Some(d) = i.1.1.description() {
+                display += &format!(" - {}", d);
             }
+            display
         })
-        .collect();
-    // By key
-    res.sort_by(|a,b| a.to_lowercase().cmp(&b.to_lowercase()));
-    return res;
+        .collect()
+}
+
+/// Run command with runner using data from to run function which extracts from config.
+/// Placeholders in the runner are expanded first, and the value is also given as `$1`.
+/// Parameters such as `{?query}` in the value or runner are asked for with the prompt,
+/// and nothing runs if one is left unanswered.
+pub fn run_command(
+    to_run: (Option<&str>, (Option<&str>, &Item)),
+    input: &str,
+    runner: Option<String>,
+    config: &Config,
+    prompt: Prompt,
+) -> Result<()> {
+    run_with_values(to_run, input, runner, config, prompt, &[to_run.1.1.value()])
 }
 
-// T is a place holder, it doesn't actually matter
-/// Run command with runner using data from to run function which extracts from config
-pub fn run_command<T>(
-    to_run: (Option<&str>, (T, &str)),
+/// run_command() with the values given as `$1..$n`. Placeholders refer to the item to run.
+fn run_with_values(
+    to_run: (Option<&str>, (Option<&str>, &Item)),
+    input: &str,
     runner: Option<String>,
     config: &Config,
+    prompt: Prompt,
+    values: &[&str],
 ) -> Result<()> {
     // 1. Check runner input
-    // 2. Check default collection runner
-    // 3. Check default config runner
-    let command_string: &str = if runner.is_some() {
-        &runner.unwrap()
-    } else {
-        match to_run.0 {
-            Some(r) => match config.collections.get(r).unwrap().default_runner.as_ref() {
-                Some(x) => x.as_str(),
-                None => config.default_runner.as_str(),
-            },
-            None => config.default_runner.as_str(),
+    // 2. Check item runner
+    // 3. Check default collection runner
+    // 4. Check default config runner
+    let collection_runner = to_run
+        .0
+        .and_then(|r| config.collections.get(r))
+        .and_then(|c| c.default_runner.as_deref());
+    let command_string: &str = config.resolve_runner(match &runner {
+        Some(r) => r,
+        None => to_run
+            .1
+            .1
+            .runner()
+            .or(collection_runner)
+            .unwrap_or(&config.default_runner),
+    })?;
+    // Typed text is not an item, so braces in it are not parameters
+    let is_item = to_run.1.0.is_some();
+    let mut names = match is_item {
+        true => template::parameters(to_run.1.1.value()),
+        false => Vec::new(),
+    };
+    for name in template::parameters(command_string) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    let mut parameters = Vec::new();
+    for name in names {
+        match prompt.ask(&name, to_run.1.1.suggestions(&name))? {
+            Some(answer) => parameters.push((name, answer)),
+            None => return Err(anyhow!("No answer for \"{}\", so nothing was run.", name)),
         }
+    }
+    let mut variables = Variables {
+        value: to_run.1.1.value(),
+        key: to_run.1.0,
+        collection: to_run.0,
+        input,
+        parameters: &parameters,
+    };
+    let fill = |v: &str| match is_item {
+        true => template::fill(v, &variables),
+        false => Ok(v.to_string()),
     };
+    let values: Vec<String> = values.iter().map(|v| fill(v)).collect::<Result<_>>()?;
+    let value = fill(to_run.1.1.value())?;
+    variables.value = &value;
+    let command_string = template::expand(command_string, &variables)?;
     let _ = Command::new("sh")
         .arg("-c")
         .arg(command_string)
         .arg("qtai")
-        .arg(to_run.1.1)
+        .args(values)
         .status()
         .context("Cannot run the command.")?;
     Ok(())
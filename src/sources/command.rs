@@ -0,0 +1,65 @@
+use anyhow::*;
+use indexmap::IndexMap;
+use serde::*;
+use std::process::Command;
+
+use super::JsonItem;
+use crate::collections::Item;
+
+/// Items printed by a shell command, e.g. `source = { command = "ls ~/projects" }`
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CommandSource {
+    pub command: String,
+    #[serde(default)]
+    pub format: Format,
+}
+
+/// How the output of a command is read
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Each line is both the key and the value
+    #[default]
+    Lines,
+    /// Each line is `key<TAB>value`. Lines without a tab are both.
+    Tsv,
+    /// Each line is a JSON object with a value and optionally a key, runner, description, icon and tags
+    Json,
+}
+
+impl CommandSource {
+    pub fn items(&self) -> Result<IndexMap<String, Item>> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .output()
+            .with_context(|| format!("Cannot run \"{}\"", self.command))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "\"{}\" failed ({}): {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let stdout = String::from_utf8(output.stdout).context("Output is not utf8")?;
+        parse(&stdout, self.format)
+    }
+}
+
+/// Parses command output into items, skipping empty lines
+pub fn parse(output: &str, format: Format) -> Result<IndexMap<String, Item>> {
+    output
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| match format {
+            Format::Lines => Ok((line.to_string(), Item::Plain(line.to_string()))),
+            Format::Tsv => Ok(match line.split_once('\t') {
+                Some((key, value)) => (key.to_string(), Item::Plain(value.to_string())),
+                None => (line.to_string(), Item::Plain(line.to_string())),
+            }),
+            Format::Json => JsonItem::parse(line),
+        })
+        .collect()
+}
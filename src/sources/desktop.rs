@@ -0,0 +1,277 @@
+use anyhow::*;
+use indexmap::IndexMap;
+use std::{env, fs, path::*};
+
+use crate::{
+    collections::{Item, ItemEntry},
+    paths::is_program,
+    template::shell_quote,
+};
+
+/// The `[Desktop Entry]` group of a `.desktop` file
+#[derive(Debug, Clone, Default)]
+struct DesktopEntry {
+    name: String,
+    comment: Option<String>,
+    icon: Option<String>,
+    exec: Option<String>,
+    try_exec: Option<String>,
+    kind: Option<String>,
+    no_display: bool,
+    hidden: bool,
+    only_show_in: Vec<String>,
+    not_show_in: Vec<String>,
+}
+
+/// Applications from the `.desktop` files of `$XDG_DATA_HOME` and `$XDG_DATA_DIRS`.
+/// Values are the `Exec` commands, ready to be run by a runner like `eval "$1"`.
+pub fn items() -> Result<IndexMap<String, Item>> {
+    let locale = locale_names();
+    let desktops: Vec<String> = env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|d| !d.is_empty())
+        .map(|d| d.to_string())
+        .collect();
+
+    // In the format of desktop file id, entry. The first file with an id wins,
+    // so user files override and hide system files.
+    let mut entries: IndexMap<String, Option<(PathBuf, DesktopEntry)>> = IndexMap::new();
+    for dir in data_dirs() {
+        let applications = dir.join("applications");
+        let pattern = applications.join("**").join("*.desktop");
+        for path in glob::glob(&pattern.to_string_lossy())?.filter_map(|p| p.ok()) {
+            // Files in sub directories get ids like `kde-okular.desktop`
+            let id = path
+                .strip_prefix(&applications)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('/', "-");
+            if entries.contains_key(&id) {
+                continue;
+            }
+            let entry = fs::read_to_string(&path)
+                .ok()
+                .and_then(|c| parse(&c, &locale))
+                .filter(|e| is_shown(e, &desktops))
+                .map(|e| (path, e));
+            entries.insert(id, entry);
+        }
+    }
+
+    let mut items: Vec<(String, Item)> = Vec::new();
+    for (id, (path, entry)) in entries.into_iter().filter_map(|e| Some((e.0, e.1?))) {
+        let Some(exec) = &entry.exec else {
+            continue;
+        };
+        let value = expand_field_codes(exec, &entry, &path);
+        // Two applications with the same name are told apart by their id
+        let key = if items.iter().any(|i| i.0 == entry.name) {
+            format!("{} ({})", entry.name, id.trim_end_matches(".desktop"))
+        } else {
+            entry.name.clone()
+        };
+        items.push((
+            key,
+            ItemEntry {
+                value,
+                description: entry.comment,
+                icon: entry.icon,
+                ..Default::default()
+            }
+            .into(),
+        ));
+    }
+    items.sort_by_key(|i| i.0.to_lowercase());
+    Ok(items.into_iter().collect())
+}
+
+/// `$XDG_DATA_HOME` followed by `$XDG_DATA_DIRS`, most important first
+fn data_dirs() -> Vec<PathBuf> {
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    dirs::data_dir()
+        .into_iter()
+        .chain(env::split_paths(&data_dirs))
+        .collect()
+}
+
+/// Keys to look for localized strings, e.g. `Name[de_DE]` then `Name[de]` for `de_DE.UTF-8`
+fn locale_names() -> Vec<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|v| env::var(v).ok())
+        .find(|l| !l.is_empty())
+        .unwrap_or_default();
+    let (locale, modifier) = match locale.split_once('@') {
+        Some((l, m)) => (l, Some(m)),
+        None => (locale.as_str(), None),
+    };
+    let locale = locale.split('.').next().unwrap_or_default();
+    let (lang, country) = match locale.split_once('_') {
+        Some((l, c)) => (l, Some(c)),
+        None => (locale, None),
+    };
+    if lang.is_empty() || lang == "C" || lang == "POSIX" {
+        return Vec::new();
+    }
+    let mut names = Vec::new();
+    if let (Some(c), Some(m)) = (country, modifier) {
+        names.push(format!("{}_{}@{}", lang, c, m));
+    }
+    if let Some(c) = country {
+        names.push(format!("{}_{}", lang, c));
+    }
+    if let Some(m) = modifier {
+        names.push(format!("{}@{}", lang, m));
+    }
+    names.push(lang.to_string());
+    names
+}
+
+/// Reads the `[Desktop Entry]` group. Entries without a name are skipped.
+fn parse(content: &str, locale: &[String]) -> Option<DesktopEntry> {
+    let mut values: IndexMap<&str, &str> = IndexMap::new();
+    let mut in_entry = false;
+    for line in content.lines().map(|l| l.trim()) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        } else if in_entry
+            && !line.starts_with('#')
+            && let Some((key, value)) = line.split_once('=')
+        {
+            values.entry(key.trim()).or_insert(value.trim());
+        }
+    }
+    let localized = |key: &str| {
+        locale
+            .iter()
+            .find_map(|l| values.get(format!("{}[{}]", key, l).as_str()))
+            .or_else(|| values.get(key))
+            .map(|v| unescape(v))
+    };
+    let list = |key: &str| {
+        values
+            .get(key)
+            .map(|v| v.split(';').filter(|d| !d.is_empty()).map(|d| d.to_string()).collect())
+            .unwrap_or_default()
+    };
+    Some(DesktopEntry {
+        name: localized("Name")?,
+        comment: localized("Comment").or_else(|| localized("GenericName")),
+        icon: values.get("Icon").map(|v| unescape(v)),
+        exec: values.get("Exec").map(|v| unescape(v)),
+        try_exec: values.get("TryExec").map(|v| unescape(v)),
+        kind: values.get("Type").map(|v| v.to_string()),
+        no_display: values.get("NoDisplay") == Some(&"true"),
+        hidden: values.get("Hidden") == Some(&"true"),
+        only_show_in: list("OnlyShowIn"),
+        not_show_in: list("NotShowIn"),
+    })
+}
+
+/// Whether an entry belongs in a menu on the current desktops
+fn is_shown(entry: &DesktopEntry, desktops: &[String]) -> bool {
+    entry.kind.as_deref().is_none_or(|k| k == "Application")
+        && !entry.no_display
+        && !entry.hidden
+        && (entry.only_show_in.is_empty() || entry.only_show_in.iter().any(|d| desktops.contains(d)))
+        && !entry.not_show_in.iter().any(|d| desktops.contains(d))
+        && entry.try_exec.as_deref().is_none_or(is_program)
+}
+
+/// Undoes the escapes of desktop entry strings
+fn unescape(value: &str) -> String {
+    let mut res = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => res.push(' '),
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('r') => res.push('\r'),
+            Some(n) => res.push(n),
+            None => res.push('\\'),
+        }
+    }
+    res
+}
+
+/// Turns `Exec` into a shell command. File and URL codes such as `%f` or `%U` are dropped,
+/// since qtai runs the application without arguments.
+fn expand_field_codes(exec: &str, entry: &DesktopEntry, path: &Path) -> String {
+    let mut res = String::new();
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => res.push('%'),
+            Some('i') => {
+                if let Some(icon) = &entry.icon {
+                    res += &format!("--icon {}", shell_quote(icon));
+                }
+            }
+            Some('c') => res += &shell_quote(&entry.name),
+            Some('k') => res += &shell_quote(&path.to_string_lossy()),
+            _ => {}
+        }
+    }
+    res.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: &str = "# Comment\n\
+        [Desktop Entry]\n\
+        Name=Files\n\
+        Name[de]=Dateien\n\
+        GenericName=File Manager\n\
+        Exec=files --new\\swindow %U\n\
+        Icon=system-file-manager\n\
+        NotShowIn=GNOME;KDE;\n\
+        Name=Ignored\n\
+        [Desktop Action new]\n\
+        Exec=ignored\n";
+
+    #[test]
+    fn entries_are_parsed_from_their_group() {
+        let entry = parse(ENTRY, &[]).unwrap();
+        assert_eq!(entry.name, "Files");
+        assert_eq!(entry.comment.as_deref(), Some("File Manager"));
+        assert_eq!(entry.exec.as_deref(), Some("files --new window %U"));
+        assert_eq!(entry.not_show_in, ["GNOME", "KDE"]);
+        assert!(entry.kind.is_none() && !entry.no_display);
+
+        let locale = ["de_DE".to_string(), "de".to_string()];
+        assert_eq!(parse(ENTRY, &locale).unwrap().name, "Dateien");
+        assert!(parse("[Desktop Entry]\nExec=a\n", &[]).is_none());
+        assert!(parse("[Other]\nName=a\n", &[]).is_none());
+    }
+
+    #[test]
+    fn field_codes_are_expanded_or_dropped() {
+        let entry = DesktopEntry {
+            name: "It's".to_string(),
+            icon: Some("icon".to_string()),
+            ..Default::default()
+        };
+        let path = Path::new("/usr/share/applications/a.desktop");
+        assert_eq!(
+            expand_field_codes("app %i %c %k 100%% %f %U", &entry, path),
+            r"app --icon 'icon' 'It'\''s' '/usr/share/applications/a.desktop' 100%"
+        );
+        let entry = DesktopEntry::default();
+        assert_eq!(expand_field_codes("app %i %F", &entry, path), "app");
+    }
+}
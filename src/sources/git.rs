@@ -0,0 +1,87 @@
+use anyhow::*;
+use indexmap::IndexMap;
+use serde::*;
+use std::{fs, path::*};
+
+use crate::{
+    collections::Item,
+    paths::{expand_tilde, file_name},
+};
+
+/// Git repositories found under some directories, e.g. `source = { git-repos = ["~/src"] }`
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GitReposSource {
+    #[serde(rename = "git-repos")]
+    pub git_repos: Vec<String>,
+    /// How many directories deep below a root a repository can be
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+}
+
+fn default_max_depth() -> usize {
+    4
+}
+
+impl GitReposSource {
+    /// Finds repositories, keyed by their path relative to the root they were found in
+    pub fn items(&self) -> Result<IndexMap<String, Item>> {
+        // In the format of key, root, path
+        let mut repos: Vec<(String, &str, PathBuf)> = Vec::new();
+        for root in &self.git_repos {
+            let root_path = expand_tilde(root);
+            let mut found = Vec::new();
+            find_repos(&root_path, self.max_depth, &mut found);
+            repos.extend(found.into_iter().map(|path| {
+                let key = match path.strip_prefix(&root_path) {
+                    anyhow::Result::Ok(p) if !p.as_os_str().is_empty() => p.to_string_lossy().to_string(),
+                    _ => file_name(&path),
+                };
+                (key, root.as_str(), path)
+            }));
+        }
+        // Repositories with the same relative path in different roots are told apart by root
+        let items = repos
+            .iter()
+            .map(|(key, root, path)| {
+                let key = if repos.iter().filter(|r| &r.0 == key).count() > 1 {
+                    format!("{} (from \"{}\")", key, root)
+                } else {
+                    key.clone()
+                };
+                (key, Item::Plain(path.to_string_lossy().to_string()))
+            })
+            .collect();
+        Ok(items)
+    }
+}
+
+/// A directory with `.git` in it, which is a file for worktrees, or a bare repository
+fn is_repo(dir: &Path) -> bool {
+    dir.join(".git").exists()
+        || (dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir())
+}
+
+/// Walks directories without following links. Repositories are not searched further.
+fn find_repos(dir: &Path, depth: usize, found: &mut Vec<PathBuf>) {
+    if is_repo(dir) {
+        found.push(dir.to_path_buf());
+        return;
+    }
+    if depth == 0 {
+        return;
+    }
+    let Some(entries) = fs::read_dir(dir).ok() else {
+        return;
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+        .map(|e| e.path())
+        .collect();
+    dirs.sort();
+    for d in dirs {
+        find_repos(&d, depth - 1, found);
+    }
+}
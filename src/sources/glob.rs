@@ -0,0 +1,139 @@
+use anyhow::*;
+use glob::{MatchOptions, Pattern};
+use indexmap::IndexMap;
+use serde::*;
+use std::path::*;
+
+use crate::{
+    collections::Item,
+    paths::{expand_tilde, file_name},
+};
+
+/// Items from paths matching glob patterns, e.g. `source = { glob = "~/projects/*/" }`
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GlobSource {
+    /// One pattern or a list of patterns to include
+    pub glob: Patterns,
+    /// Patterns to leave out. Patterns without a `/` are matched against the file name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// How many directories deep below the start of the pattern a match can be
+    pub max_depth: Option<usize>,
+    /// Whether wildcards match files starting with a dot
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub key: KeyStyle,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum Patterns {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// How keys are made from paths
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStyle {
+    /// File name, e.g. `qtai` for `~/projects/qtai/`
+    #[default]
+    Basename,
+    /// File name without its extension
+    Stem,
+    /// Path relative to the start of the pattern
+    Relative,
+    /// Whole path
+    Path,
+}
+
+impl GlobSource {
+    pub fn items(&self) -> Result<IndexMap<String, Item>> {
+        let patterns = match &self.glob {
+            Patterns::One(p) => std::slice::from_ref(p),
+            Patterns::Many(p) => p.as_slice(),
+        };
+        let exclude = self
+            .exclude
+            .iter()
+            .map(|e| Pattern::new(&expand_tilde(e).to_string_lossy()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Invalid exclude pattern")?;
+        let options = MatchOptions {
+            require_literal_leading_dot: !self.hidden,
+            ..MatchOptions::new()
+        };
+
+        // In the format of key, path
+        let mut matches: Vec<(String, PathBuf)> = Vec::new();
+        for pattern in patterns {
+            let pattern = expand_tilde(pattern).to_string_lossy().to_string();
+            let base = base_dir(&pattern);
+            for path in glob::glob_with(&pattern, options)
+                .with_context(|| format!("Invalid glob pattern \"{}\"", pattern))?
+                .filter_map(|p| p.ok())
+            {
+                let relative = path.strip_prefix(&base).unwrap_or(&path);
+                if self.max_depth.is_some_and(|d| relative.components().count() > d)
+                    || exclude.iter().any(|e| is_excluded(e, &path))
+                    || matches.iter().any(|m| m.1 == path)
+                {
+                    continue;
+                }
+                let key = match self.key {
+                    KeyStyle::Basename => file_name(&path),
+                    KeyStyle::Stem => path
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_else(|| file_name(&path)),
+                    KeyStyle::Relative => relative.to_string_lossy().to_string(),
+                    KeyStyle::Path => path.to_string_lossy().to_string(),
+                };
+                matches.push((key, path));
+            }
+        }
+        Ok(disambiguate(matches))
+    }
+}
+
+/// The directory before the first wildcard of a pattern
+fn base_dir(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .collect()
+}
+
+fn is_excluded(pattern: &Pattern, path: &Path) -> bool {
+    if pattern.as_str().contains('/') {
+        pattern.matches_path(path)
+    } else {
+        pattern.matches(&file_name(path))
+    }
+}
+
+/// Labels keys which show up more than once with their parent directory,
+/// like run::display_pairs does with collections
+fn disambiguate(matches: Vec<(String, PathBuf)>) -> IndexMap<String, Item> {
+    let mut has_duplicate = IndexMap::new();
+    for i in &matches {
+        has_duplicate
+            .entry(i.0.clone())
+            .and_modify(|e| *e = true)
+            .or_insert(false);
+    }
+    matches
+        .into_iter()
+        .map(|(key, path)| {
+            let key = if has_duplicate[&key] {
+                let parent = path.parent().unwrap_or(Path::new("/"));
+                format!("{} (from \"{}\")", key, parent.display())
+            } else {
+                key
+            };
+            (key, Item::Plain(path.to_string_lossy().to_string()))
+        })
+        .collect()
+}
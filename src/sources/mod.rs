@@ -0,0 +1,98 @@
+pub mod command;
+pub mod desktop;
+pub mod git;
+pub mod glob;
+pub mod plugin;
+pub mod ssh;
+pub mod xbel;
+
+use anyhow::*;
+use indexmap::IndexMap;
+use serde::*;
+use std::time::Duration;
+
+use crate::{
+    cache,
+    collections::{Item, ItemEntry},
+};
+
+pub use command::CommandSource;
+pub use git::GitReposSource;
+pub use glob::GlobSource;
+pub use plugin::PluginSource;
+pub use xbel::XbelSource;
+
+/// Where a collection gets generated items from, next to its static items
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum Source {
+    Command(CommandSource),
+    Glob(GlobSource),
+    GitRepos(GitReposSource),
+    Xbel(XbelSource),
+    Plugin(PluginSource),
+    Builtin(Builtin),
+}
+
+/// Sources which need no settings, e.g. `source = "desktop-entries"`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Builtin {
+    /// Applications from `.desktop` files
+    DesktopEntries,
+    /// Hosts from `~/.ssh/config`
+    SshConfig,
+}
+
+impl Source {
+    /// Generates the items, going through the cache when the collection has a `cache_ttl`.
+    /// Git repositories are slow to find, so they are always cached and refreshed in the background.
+    pub fn cached_items(&self, collection: &str, cache_ttl: Option<u64>) -> Result<IndexMap<String, Item>> {
+        let ttl = match (cache_ttl, self) {
+            (Some(t), _) => t,
+            (None, Source::GitRepos(_)) => 0,
+            (None, _) => return self.items(),
+        };
+        match cache::cache_file(collection, self) {
+            Some(path) => {
+                let source = self.clone();
+                cache::stale_while_revalidate(path, collection, Duration::from_secs(ttl), move || {
+                    source.items()
+                })
+            }
+            None => self.items(),
+        }
+    }
+
+    /// Generates the items of the source
+    pub fn items(&self) -> Result<IndexMap<String, Item>> {
+        match self {
+            Source::Command(c) => c.items(),
+            Source::Glob(g) => g.items(),
+            Source::GitRepos(g) => g.items(),
+            Source::Xbel(x) => x.items(),
+            Source::Plugin(p) => p.items(),
+            Source::Builtin(Builtin::DesktopEntries) => desktop::items(),
+            Source::Builtin(Builtin::SshConfig) => ssh::items(),
+        }
+    }
+}
+
+/// An item printed as a JSON line, e.g. `{"key": "github", "value": "https://github.com"}`.
+/// The key defaults to the value.
+#[derive(Deserialize, Debug, Clone)]
+pub struct JsonItem {
+    pub key: Option<String>,
+    #[serde(flatten)]
+    pub entry: ItemEntry,
+}
+
+impl JsonItem {
+    /// Parses one JSON line into a key and an item
+    pub fn parse(line: &str) -> Result<(String, Item)> {
+        let item: JsonItem =
+            serde_json::from_str(line).with_context(|| format!("Invalid JSON item: {}", line))?;
+        let key = item.key.unwrap_or_else(|| item.entry.value.clone());
+        Ok((key, item.entry.into()))
+    }
+}
@@ -0,0 +1,164 @@
+use anyhow::*;
+use indexmap::IndexMap;
+use serde::*;
+use std::{
+    io::Read,
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::JsonItem;
+use crate::{collections::Item, paths::expand_tilde};
+
+/// Newest plugin protocol this qtai understands
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Items from a plugin program, e.g. `source = { plugin = "jira", args = ["--mine"] }`
+/// runs `qtai-source-jira --mine` from PATH.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PluginSource {
+    /// Name of the plugin, or a path to it
+    pub plugin: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Seconds to wait for the plugin before giving up
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+/// First line a plugin can print to say which protocol it speaks, e.g. `{"protocol": 1}`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct Header {
+    protocol: u32,
+}
+
+impl PluginSource {
+    /// Program to run, `qtai-source-<name>` unless the plugin is a path
+    pub fn program(&self) -> String {
+        if self.plugin.contains('/') {
+            expand_tilde(&self.plugin).to_string_lossy().to_string()
+        } else {
+            format!("qtai-source-{}", self.plugin)
+        }
+    }
+
+    /// Runs the plugin with `QTAI_PROTOCOL` set, and reads a JSON item from each line it prints
+    pub fn items(&self) -> Result<IndexMap<String, Item>> {
+        let program = self.program();
+        let mut child = Command::new(&program)
+            .args(&self.args)
+            .env("QTAI_PROTOCOL", PROTOCOL_VERSION.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // A group of its own, so a timeout also stops what a shell script plugin started
+            .process_group(0)
+            .spawn()
+            .with_context(|| format!("Cannot run plugin \"{}\"", program))?;
+
+        // Read while waiting, so a plugin printing a lot does not block on a full pipe
+        let read = |pipe: Option<Box<dyn Read + Send>>| -> Receiver<String> {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                let mut text = String::new();
+                if let Some(mut p) = pipe {
+                    let _ = p.read_to_string(&mut text);
+                }
+                let _ = sender.send(text);
+            });
+            receiver
+        };
+        let stdout = read(child.stdout.take().map(|p| Box::new(p) as _));
+        let stderr = read(child.stderr.take().map(|p| Box::new(p) as _));
+
+        let deadline = Instant::now() + Duration::from_secs(self.timeout);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() > deadline {
+                let _ = Command::new("kill")
+                    .args(["-KILL", "--", &format!("-{}", child.id())])
+                    .stderr(Stdio::null())
+                    .status();
+                let _ = child.kill();
+                let _ = child.wait();
+                // Something outside the group can still hold the pipe, so it is not waited for long
+                let stderr = stderr.recv_timeout(Duration::from_millis(100)).unwrap_or_default();
+                return Err(anyhow!(with_stderr(
+                    format!("Plugin \"{}\" took longer than {} seconds", program, self.timeout),
+                    &stderr
+                )));
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let stdout = stdout.recv().unwrap_or_default();
+        let stderr = stderr.recv().unwrap_or_default();
+        if !status.success() {
+            return Err(anyhow!(with_stderr(
+                format!("Plugin \"{}\" failed ({})", program, status),
+                &stderr
+            )));
+        }
+        parse(&stdout).with_context(|| {
+            with_stderr(format!("Plugin \"{}\" printed bad output", program), &stderr)
+        })
+    }
+}
+
+/// Adds what the plugin printed to stderr to an error message
+fn with_stderr(message: String, stderr: &str) -> String {
+    match stderr.trim() {
+        "" => message,
+        s => format!("{} (stderr: {})", message, s),
+    }
+}
+
+/// Reads an optional protocol header, then one JSON item per line
+fn parse(output: &str) -> Result<IndexMap<String, Item>> {
+    let mut lines = output.lines().filter(|l| !l.trim().is_empty()).peekable();
+    if let Some(header) = lines.peek().and_then(|l| serde_json::from_str::<Header>(l).ok()) {
+        if header.protocol > PROTOCOL_VERSION {
+            return Err(anyhow!(
+                "Plugin speaks protocol {}, but this qtai only knows up to protocol {}. Try updating qtai.",
+                header.protocol,
+                PROTOCOL_VERSION
+            ));
+        }
+        lines.next();
+    }
+    lines.map(JsonItem::parse).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_follow_an_optional_header() {
+        let output = "{\"protocol\": 1}\n\n{\"key\": \"gh\", \"value\": \"https://github.com\"}\n{\"value\": \"plain\"}\n";
+        let items = parse(output).unwrap();
+        assert_eq!(items.keys().collect::<Vec<_>>(), ["gh", "plain"]);
+        assert_eq!(items["gh"].value(), "https://github.com");
+        assert_eq!(parse("{\"value\": \"a\"}").unwrap().len(), 1);
+        assert!(parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn newer_protocols_and_bad_lines_fail() {
+        let error = parse("{\"protocol\": 2}\n{\"value\": \"a\"}\n").unwrap_err();
+        assert!(error.to_string().contains("protocol 2"));
+        assert!(parse("{\"value\": \"a\"}\nnot json\n").is_err());
+        // A header only counts on the first line
+        assert!(parse("{\"value\": \"a\"}\n{\"protocol\": 1}\n").is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::*;
+use indexmap::IndexMap;
+use std::{fs, path::*};
+
+use crate::{
+    collections::{Item, ItemEntry},
+    paths::expand_tilde,
+};
+
+/// Includes deeper than this are most likely a loop
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A `Host` block of an ssh config
+#[derive(Debug, Clone, Default)]
+struct Host {
+    aliases: Vec<String>,
+    host_name: Option<String>,
+    user: Option<String>,
+}
+
+/// Hosts from `~/.ssh/config`. Keys and values are the host aliases,
+/// and `HostName` and `User` become the description.
+pub fn items() -> Result<IndexMap<String, Item>> {
+    let config = expand_tilde("~/.ssh/config");
+    let mut hosts = Vec::new();
+    read_hosts(&config, 0, &mut hosts)?;
+
+    let mut items = IndexMap::new();
+    for host in hosts {
+        let description = match (&host.user, &host.host_name) {
+            (Some(u), Some(h)) => Some(format!("{}@{}", u, h)),
+            (Some(u), None) => Some(format!("{}@", u)),
+            (None, h) => h.clone(),
+        };
+        for alias in host.aliases {
+            // Like ssh, the first block for a host wins
+            items.entry(alias.clone()).or_insert_with(|| {
+                Item::from(ItemEntry {
+                    value: alias,
+                    description: description.clone(),
+                    ..Default::default()
+                })
+            });
+        }
+    }
+    Ok(items)
+}
+
+/// Reads the hosts of a file and the files it includes, in order
+fn read_hosts(path: &Path, depth: usize, hosts: &mut Vec<Host>) -> Result<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(anyhow!("Too many nested includes at {}", path.display()));
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Cannot read ssh config {}", path.display()))?;
+    // Blocks from `Match` are not hosts, so their settings go nowhere
+    let mut current: Option<Host> = None;
+    // Hosts of included files, which come after the block they are included in
+    let mut included: Vec<Host> = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Keywords are separated from arguments by spaces or `=`
+        let (keyword, args) = line
+            .split_once(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or((line, ""));
+        let args = args.trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim();
+        match keyword.to_ascii_lowercase().as_str() {
+            "host" => {
+                hosts.extend(current.take());
+                hosts.append(&mut included);
+                current = Some(Host {
+                    aliases: words(args)
+                        .into_iter()
+                        .filter(|a| !a.contains(['*', '?', '!']))
+                        .collect(),
+                    ..Host::default()
+                });
+            }
+            "match" => {
+                hosts.extend(current.take());
+                hosts.append(&mut included);
+            }
+            "hostname" => {
+                if let Some(h) = current.as_mut().filter(|h| h.host_name.is_none()) {
+                    h.host_name = words(args).into_iter().next();
+                }
+            }
+            "user" => {
+                if let Some(h) = current.as_mut().filter(|h| h.user.is_none()) {
+                    h.user = words(args).into_iter().next();
+                }
+            }
+            "include" => {
+                for pattern in words(args) {
+                    for file in include_files(&pattern)? {
+                        read_hosts(&file, depth + 1, &mut included)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    hosts.extend(current);
+    hosts.append(&mut included);
+    Ok(())
+}
+
+/// Files of an `Include`. Relative paths are relative to `~/.ssh`.
+fn include_files(pattern: &str) -> Result<Vec<PathBuf>> {
+    let path = expand_tilde(pattern);
+    let path = if path.is_relative() {
+        expand_tilde("~/.ssh").join(path)
+    } else {
+        path
+    };
+    Ok(glob::glob(&path.to_string_lossy())
+        .with_context(|| format!("Invalid Include pattern \"{}\"", pattern))?
+        .filter_map(|p| p.ok())
+        .filter(|p| p.is_file())
+        .collect())
+}
+
+/// Splits arguments on spaces, keeping double quoted arguments together
+fn words(args: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in args.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_keep_quoted_arguments_together() {
+        assert_eq!(words("  a \"b c\"\td "), ["a", "b c", "d"]);
+        assert!(words("").is_empty());
+    }
+
+    #[test]
+    fn hosts_are_read_in_order_with_includes() {
+        let dir = std::env::temp_dir().join(format!("qtai-ssh-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let included = dir.join("included");
+        fs::write(&included, "Host inner\n  HostName inner.example\n").unwrap();
+        let config = dir.join("config");
+        fs::write(
+            &config,
+            format!(
+                "# Comment\nUser ignored\nHost web *.example !bad\n  HostName=web.example\n  User admin\n  \
+                 User second\n  Include \"{}\"\nMatch host web\n  User matched\nhost \"db one\"\n",
+                included.display()
+            ),
+        )
+        .unwrap();
+
+        let mut hosts = Vec::new();
+        let result = read_hosts(&config, 0, &mut hosts);
+        fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+        let hosts: Vec<_> = hosts
+            .iter()
+            .map(|h| (h.aliases.join(","), h.host_name.as_deref(), h.user.as_deref()))
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                ("web".to_string(), Some("web.example"), Some("admin")),
+                ("inner".to_string(), Some("inner.example"), None),
+                ("db one".to_string(), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn include_loops_stop() {
+        let dir = std::env::temp_dir().join(format!("qtai-ssh-loop-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config");
+        fs::write(&config, format!("Include {}\n", config.display())).unwrap();
+        let result = read_hosts(&config, 0, &mut Vec::new());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::*;
+use indexmap::IndexMap;
+use serde::*;
+use std::fs;
+
+use crate::{
+    collections::{Item, ItemEntry},
+    markup::{attribute, decode_entities, next_tag, tag_name, text_until},
+    paths::expand_tilde,
+    template::urldecode,
+};
+
+/// Bookmarks or recently used files from an XBEL file,
+/// e.g. `source = { xbel = "~/.local/share/recently-used.xbel", sort = "visited" }`
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct XbelSource {
+    pub xbel: String,
+    #[serde(default)]
+    pub sort: Sort,
+}
+
+/// Order of the items
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    /// As they are in the file
+    #[default]
+    File,
+    /// Most recently visited first
+    Visited,
+    /// Most recently modified first
+    Modified,
+    /// Most recently added first
+    Added,
+}
+
+/// A `<bookmark>` of an XBEL file
+#[derive(Debug, Clone, Default)]
+struct Bookmark {
+    href: String,
+    title: Option<String>,
+    added: String,
+    modified: String,
+    visited: String,
+    mime_type: Option<String>,
+    applications: Vec<String>,
+}
+
+impl XbelSource {
+    /// Values are URIs, with `file://` URIs turned into paths.
+    /// The MIME type and applications become the description and tags.
+    pub fn items(&self) -> Result<IndexMap<String, Item>> {
+        let path = expand_tilde(&self.xbel);
+        let xml = fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read XBEL file {}", path.display()))?;
+        let mut bookmarks = parse(&xml);
+        // Timestamps are ISO 8601, so they sort as text
+        match self.sort {
+            Sort::File => {}
+            Sort::Visited => bookmarks.sort_by(|a, b| b.visited.cmp(&a.visited)),
+            Sort::Modified => bookmarks.sort_by(|a, b| b.modified.cmp(&a.modified)),
+            Sort::Added => bookmarks.sort_by(|a, b| b.added.cmp(&a.added)),
+        }
+
+        let mut items = IndexMap::new();
+        for b in bookmarks {
+            let value = match b.href.strip_prefix("file://") {
+                // The host, usually empty or localhost, is dropped
+                Some(p) => urldecode(&p[p.find('/').unwrap_or(0)..]),
+                None => b.href.clone(),
+            };
+            let name = b.title.filter(|t| !t.is_empty()).unwrap_or_else(|| {
+                let name = value.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+                if name.is_empty() { value.clone() } else { name.to_string() }
+            });
+            // Files with the same name are told apart by their whole path
+            let key = if items.contains_key(&name) {
+                format!("{} ({})", name, value)
+            } else {
+                name
+            };
+            let description = match (&b.mime_type, b.applications.is_empty()) {
+                (Some(m), false) => Some(format!("{} ({})", m, b.applications.join(", "))),
+                (Some(m), true) => Some(m.clone()),
+                (None, false) => Some(b.applications.join(", ")),
+                (None, true) => None,
+            };
+            let tags = b.mime_type.into_iter().chain(b.applications).collect();
+            items.insert(
+                key,
+                Item::from(ItemEntry {
+                    value,
+                    description,
+                    tags,
+                    ..Default::default()
+                }),
+            );
+        }
+        Ok(items)
+    }
+}
+
+/// Reads every bookmark, including the ones in folders
+fn parse(xml: &str) -> Vec<Bookmark> {
+    let mut bookmarks = Vec::new();
+    let mut current: Option<Bookmark> = None;
+    let mut rest = xml;
+    while let Some(tag) = next_tag(&mut rest) {
+        let value = |name: &str| attribute(tag, name).map(|v| decode_entities(&v));
+        match (tag_name(tag).as_str(), current.as_mut()) {
+            ("bookmark", _) => {
+                bookmarks.extend(current.take());
+                current = Some(Bookmark {
+                    href: value("href").unwrap_or_default(),
+                    added: value("added").unwrap_or_default(),
+                    modified: value("modified").unwrap_or_default(),
+                    visited: value("visited").unwrap_or_default(),
+                    ..Bookmark::default()
+                });
+                if tag.ends_with('/') {
+                    bookmarks.extend(current.take());
+                }
+            }
+            ("/bookmark", _) => bookmarks.extend(current.take()),
+            ("title", Some(b)) => b.title = Some(decode_entities(&text_until(&mut rest, "</title>"))),
+            ("mime:mime-type", Some(b)) => b.mime_type = value("type"),
+            ("bookmark:application", Some(b)) => b.applications.extend(value("name")),
+            _ => {}
+        }
+    }
+    bookmarks.extend(current);
+    bookmarks.retain(|b| !b.href.is_empty());
+    bookmarks
+}
@@ -0,0 +1,298 @@
+use anyhow::*;
+
+/// Data available to placeholders such as `{value}` or `{input|urlencode}` when running an item
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Variables<'a> {
+    pub value: &'a str,
+    pub key: Option<&'a str>,
+    pub collection: Option<&'a str>,
+    /// Typed text, or the key of the picked item
+    pub input: &'a str,
+    /// Answers to the parameters of the item, in the format of name, answer
+    pub parameters: &'a [(String, String)],
+}
+
+impl Variables<'_> {
+    fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "value" => Some(self.value),
+            "key" => Some(self.key.unwrap_or("")),
+            "collection" => Some(self.collection.unwrap_or("")),
+            "input" => Some(self.input),
+            _ => {
+                let name = name.strip_prefix('?')?;
+                self.parameters.iter().find(|p| p.0 == name).map(|p| p.1.as_str())
+            }
+        }
+    }
+}
+
+/// Where a placeholder sits in the runner, which decides how it is escaped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quoting {
+    None,
+    Single,
+    Double,
+}
+
+/// Expands placeholders in a runner. Expanded text is escaped for the quotes around the placeholder,
+/// so it can never change the shell command, unless the `raw` filter is used.
+/// Braces which are not placeholders, such as `${1}` or awk programs, are kept as is.
+pub fn expand(runner: &str, variables: &Variables) -> Result<String> {
+    expand_text(runner, variables, true)
+}
+
+/// Expands placeholders in an item value. Nothing is escaped, since the value is not a shell command.
+pub fn fill(value: &str, variables: &Variables) -> Result<String> {
+    expand_text(value, variables, false)
+}
+
+/// Names of the parameters in a runner or value, such as `query` in `{?query|urlencode}`.
+/// Parameters are marked with `?`, so braces of shell, awk or jq are never mistaken for them.
+pub fn parameters(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{?") {
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        let name = rest[..end].split('|').next().unwrap_or("").trim();
+        let is_identifier = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        if is_identifier && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+fn expand_text(runner: &str, variables: &Variables, shell: bool) -> Result<String> {
+    let chars: Vec<char> = runner.chars().collect();
+    let mut res = String::new();
+    let mut quoting = Quoting::None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match (c, quoting) {
+            ('\\', Quoting::None | Quoting::Double) if shell => {
+                res.push(c);
+                if let Some(next) = chars.get(i + 1) {
+                    res.push(*next);
+                }
+                i += 2;
+                continue;
+            }
+            ('\'', Quoting::None) if shell => quoting = Quoting::Single,
+            ('\'', Quoting::Single) => quoting = Quoting::None,
+            ('"', Quoting::None) if shell => quoting = Quoting::Double,
+            ('"', Quoting::Double) => quoting = Quoting::None,
+            ('{', _) if i == 0 || chars[i - 1] != '$' => {
+                let end = chars[i..].iter().position(|c| *c == '}').map(|e| e + i);
+                if let Some(end) = end {
+                    let inner: String = chars[i + 1..end].iter().collect();
+                    if let Some(expanded) = expand_placeholder(&inner, variables, quoting, shell)? {
+                        res += &expanded;
+                        i = end + 1;
+                        continue;
+                    }
+                }
+            }
+            _ => {}
+        }
+        res.push(c);
+        i += 1;
+    }
+    Ok(res)
+}
+
+/// Expands `name|filter|filter`, or returns None if it is not a placeholder
+fn expand_placeholder(
+    inner: &str,
+    variables: &Variables,
+    quoting: Quoting,
+    shell: bool,
+) -> Result<Option<String>> {
+    let mut parts = inner.split('|').map(|p| p.trim());
+    let Some(mut text) = parts.next().and_then(|name| variables.get(name)).map(|t| t.to_string())
+    else {
+        return Ok(None);
+    };
+    let mut raw = false;
+    // Text quoted by the last filter is already one shell word
+    let mut quoted = false;
+    for filter in parts {
+        quoted = filter == "shell";
+        text = match filter {
+            "urlencode" => urlencode(&text),
+            "shell" => shell_quote(&text),
+            "lower" => text.to_lowercase(),
+            "upper" => text.to_uppercase(),
+            "trim" => text.trim().to_string(),
+            "raw" => {
+                raw = true;
+                text
+            }
+            _ => return Err(anyhow!("Unknown filter \"{}\" in \"{{{}}}\".", filter, inner)),
+        };
+    }
+    if raw || !shell {
+        return Ok(Some(text));
+    }
+    Ok(Some(match quoting {
+        Quoting::None if quoted => text,
+        Quoting::None => shell_quote(&text),
+        Quoting::Single => text.replace('\'', "'\\''"),
+        Quoting::Double => text
+            .chars()
+            .flat_map(|c| match c {
+                '\\' | '"' | '$' | '`' => vec!['\\', c],
+                _ => vec![c],
+            })
+            .collect(),
+    }))
+}
+
+/// Quotes text so the shell reads it as one word
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Percent-encodes everything except unreserved URL characters
+pub fn urlencode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Decodes `%XX` escapes, such as the ones in `file://` URIs
+pub fn urldecode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut res = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = text.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                res.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                res.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&res).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn variables(value: &str) -> Variables<'_> {
+        Variables {
+            value,
+            key: Some("key"),
+            collection: Some("collection"),
+            input: "typed text",
+            ..Default::default()
+        }
+    }
+
+    /// What the shell prints for the expanded runner
+    fn shell_output(runner: &str, value: &str) -> String {
+        let command = expand(runner, &variables(value)).unwrap();
+        let output = Command::new("sh").arg("-c").arg(command).output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn unquoted_placeholders_are_one_word() {
+        assert_eq!(expand("echo {value}", &variables("a b")).unwrap(), "echo 'a b'");
+        assert_eq!(shell_output(r#"printf "[%s]" {value}"#, "it's $HOME"), "[it's $HOME]");
+    }
+
+    #[test]
+    fn single_quoted_placeholders_are_escaped() {
+        assert_eq!(expand("echo '{value}'", &variables("it's")).unwrap(), r"echo 'it'\''s'");
+        assert_eq!(shell_output(r#"printf "[%s]" '{value}'"#, "it's `x`"), "[it's `x`]");
+    }
+
+    #[test]
+    fn double_quoted_placeholders_are_escaped() {
+        assert_eq!(expand(r#"echo "{value}""#, &variables(r#"$a "b""#)).unwrap(), r#"echo "\$a \"b\"""#);
+        assert_eq!(shell_output(r#"printf "[%s]" "{value}""#, r#"$(x) "\"#), r#"[$(x) "\]"#);
+    }
+
+    #[test]
+    fn shell_filter_quotes_once() {
+        assert_eq!(expand("echo {value|shell}", &variables("a b")).unwrap(), "echo 'a b'");
+        assert_eq!(shell_output(r#"printf "[%s]\n" {value|shell}"#, "hello world"), "[hello world]\n");
+        // Inside quotes the quoted text is kept for a nested shell
+        assert_eq!(shell_output(r#"sh -c "printf [%s] {value|shell}""#, "a b"), "[a b]");
+        assert_eq!(shell_output("printf [%s] '{value|shell}'", "a b"), "['a b']");
+    }
+
+    #[test]
+    fn filters_apply_in_order() {
+        assert_eq!(expand("{input|upper|raw}", &variables("")).unwrap(), "TYPED TEXT");
+        assert_eq!(expand("{ key | raw }", &variables("")).unwrap(), "key");
+        assert_eq!(expand("{value|trim|urlencode}", &variables(" a&b ")).unwrap(), "'a%26b'");
+        assert!(expand("{value|nope}", &variables("")).is_err());
+    }
+
+    #[test]
+    fn other_braces_are_kept() {
+        let runner = "awk '{print $1}' ${1} {unknown} {collection}";
+        assert_eq!(
+            expand(runner, &variables("")).unwrap(),
+            "awk '{print $1}' ${1} {unknown} 'collection'"
+        );
+    }
+
+    #[test]
+    fn parameters_are_marked_placeholders() {
+        let runner = "curl {value}/{?query|urlencode}?page={?page } {?query} {?2x} {query}";
+        assert_eq!(parameters(runner), ["query", "page"]);
+    }
+
+    #[test]
+    fn shell_awk_and_jq_braces_are_not_parameters() {
+        for runner in [r#"echo "$1" | awk '{print}'"#, "jq '{name}' \"$1\"", "echo ${HOME} {a b}"] {
+            assert!(parameters(runner).is_empty(), "{}", runner);
+            assert_eq!(expand(runner, &variables("")).unwrap(), runner);
+        }
+    }
+
+    #[test]
+    fn parameter_answers_are_filled_in() {
+        let answers = [("query".to_string(), "a b".to_string())];
+        let variables = Variables {
+            parameters: &answers,
+            ..variables("https://example.com")
+        };
+        assert_eq!(fill("{value}/?q={?query|urlencode}", &variables).unwrap(), "https://example.com/?q=a%20b");
+        assert_eq!(expand("echo {?query} {?page} {query}", &variables).unwrap(), "echo 'a b' {?page} {query}");
+    }
+
+    #[test]
+    fn urlencode_keeps_unreserved_characters() {
+        assert_eq!(urlencode("a-z_0.9~"), "a-z_0.9~");
+        assert_eq!(urlencode("a b/é?"), "a%20b%2F%C3%A9%3F");
+    }
+
+    #[test]
+    fn urldecode_reverses_urlencode() {
+        assert_eq!(urldecode("/home/me/My%20Files/%C3%A9.txt"), "/home/me/My Files/é.txt");
+        assert_eq!(urldecode(&urlencode("a b/é?")), "a b/é?");
+        // Broken escapes stay as they are
+        assert_eq!(urldecode("100% %zz %4"), "100% %zz %4");
+    }
+}